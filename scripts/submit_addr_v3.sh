@@ -13,7 +13,7 @@ CHAIN_ID=$(curl -s http://localhost:8001/chain | jq -r .chain_id)
 
 PAYLOAD_SELF=$(printf '%s' "$PAYLOAD_RAW" | jq --arg a "$ADDR" '(.args.to == "SELF") as $is | if $is then .args.to = $a else . end')
 
-NONCE=$(curl -s http://localhost:8001/nonce/"$ADDR" | jq -r .next_nonce)
+NONCE=$(curl -s http://localhost:8001/nonce/"$ADDR" | jq -r .pending_nonce)
 
 CANON=$(curl -s http://localhost:8001/canonical -H 'content-type: application/json' -d @- <<JSON | jq -r .payload
 {
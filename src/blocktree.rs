@@ -0,0 +1,55 @@
+use crate::contracts::Ctx;
+use crate::types::{Block, Receipt};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Post-state of a block, kept so a reorg can restart execution from it.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub ctx: Ctx,
+    pub nonces: HashMap<String, u64>,
+    pub receipts: Vec<Receipt>,
+}
+
+/// Every recent valid block we know of, canonical or not, keyed by hash.
+#[derive(Default)]
+pub struct BlockTree {
+    blocks: HashMap<String, (Block, Snapshot)>,
+}
+
+/// PoA fork choice: the higher block wins; at equal height the one from the
+/// earlier slot (fewer skipped leaders) wins, then the lower hash.
+pub fn is_better(a: &Block, b: &Block) -> bool {
+    (a.height, Reverse(a.slot), Reverse(&a.hash)) > (b.height, Reverse(b.slot), Reverse(&b.hash))
+}
+
+impl BlockTree {
+    pub fn insert(&mut self, block: Block, snapshot: Snapshot) {
+        self.blocks.insert(block.hash.clone(), (block, snapshot));
+    }
+    pub fn contains(&self, hash: &str) -> bool { self.blocks.contains_key(hash) }
+    pub fn get(&self, hash: &str) -> Option<&Block> {
+        self.blocks.get(hash).map(|(b, _)| b)
+    }
+    pub fn snapshot(&self, hash: &str) -> Option<&Snapshot> {
+        self.blocks.get(hash).map(|(_, s)| s)
+    }
+
+    /// Blocks from `tip` back to, but excluding, the first block for which
+    /// `is_canonical` holds; oldest first. `None` if the walk leaves the tree.
+    pub fn branch(&self, tip: &str, is_canonical: impl Fn(&Block) -> bool) -> Option<Vec<Block>> {
+        let mut out = Vec::new();
+        let mut cur = self.get(tip)?;
+        while !is_canonical(cur) {
+            out.push(cur.clone());
+            cur = self.get(&cur.parent)?;
+        }
+        out.reverse();
+        Some(out)
+    }
+
+    /// Forgets blocks below `min_height`; they can no longer be reorged.
+    pub fn prune(&mut self, min_height: u64) {
+        self.blocks.retain(|_, (b, _)| b.height >= min_height);
+    }
+}
@@ -1,6 +1,7 @@
 use serde::{Serialize, Deserialize};
-use crate::types::Block;
+use crate::types::{Block, Evidence, FinalityCert, Precommit, SignedHeader};
 use crate::pq;
+use crate::util::{hash_hex, now_ms};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Validator {
@@ -13,6 +14,16 @@ pub struct Validator {
 pub struct PoAConfig {
     pub validators: Vec<Validator>,
     pub slot_ms: u64,
+    /// Start of slot 0; the genesis block timestamp (`QSC_GENESIS_MS`).
+    pub genesis_ms: u128,
+    /// How far into the future a block timestamp may be (`QSC_MAX_DRIFT_MS`).
+    pub max_drift_ms: u128,
+    /// Bonded stake per validator under `QSC_CONSENSUS=pos`; empty means
+    /// plain round-robin.
+    pub stakes: Vec<u64>,
+    /// Nodes that blocks, votes and sync requests go to: the validators from
+    /// `QSC_VALIDATORS_JSON` plus any that joined on chain.
+    pub peers: Vec<Validator>,
 }
 
 impl PoAConfig {
@@ -20,26 +31,268 @@ impl PoAConfig {
         let raw = std::env::var("QSC_VALIDATORS_JSON").ok()?;
         let validators: Vec<Validator> = serde_json::from_str(&raw).ok()?;
         let slot_ms = std::env::var("QSC_SLOT_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(3000);
-        Some(Self { validators, slot_ms })
+        let genesis_ms = std::env::var("QSC_GENESIS_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+        let max_drift_ms = std::env::var("QSC_MAX_DRIFT_MS").ok().and_then(|s| s.parse().ok()).unwrap_or(500);
+        Some(Self { peers: validators.clone(), validators, slot_ms, genesis_ms, max_drift_ms, stakes: Vec::new() })
     }
-    pub fn expected_leader(&self, height_next: u64) -> &Validator {
+    /// Uses `validators` as the active set, keeping those with a usable URL
+    /// reachable as peers.
+    pub fn set_validators(&mut self, validators: Vec<Validator>) {
+        for v in &validators {
+            if !is_valid_url(&v.url) { continue; }
+            if !self.peers.iter().any(|p| p.pk.eq_ignore_ascii_case(&v.pk)) { self.peers.push(v.clone()); }
+        }
+        self.validators = validators;
+    }
+    /// Finality voters with their voting power: their stake when the set is
+    /// stake-weighted, otherwise one each.
+    pub fn voters(&self) -> Vec<(String, u64)> {
+        let weighted = self.stakes.len() == self.validators.len();
+        self.validators.iter().enumerate()
+            .map(|(i, v)| (v.pk.clone(), if weighted { self.stakes[i] } else { 1 }))
+            .collect()
+    }
+    pub fn slot_at(&self, ts_ms: u128) -> u64 {
+        (ts_ms.saturating_sub(self.genesis_ms) / self.slot_ms.max(1) as u128) as u64
+    }
+    pub fn slot_start(&self, slot: u64) -> u128 {
+        self.genesis_ms + slot as u128 * self.slot_ms as u128
+    }
+    /// Round-robin over slots rather than heights: if the leader of a slot is
+    /// offline, the leader of the next slot extends the chain instead.
+    pub fn expected_leader(&self, slot: u64) -> &Validator {
         let n = self.validators.len().max(1);
-        &self.validators[(height_next as usize) % n]
+        &self.validators[(slot as usize) % n]
+    }
+    /// Leader for `slot` on top of `parent`. With stakes, each validator is
+    /// picked with probability proportional to its stake, using the parent
+    /// hash and slot as a seed every node agrees on.
+    pub fn leader(&self, parent: &Block, slot: u64) -> &Validator {
+        let total: u128 = self.stakes.iter().map(|&s| s as u128).sum();
+        if total == 0 || self.stakes.len() != self.validators.len() {
+            return self.expected_leader(slot);
+        }
+        let seed = hash_hex(format!("{}|{}", parent.hash, slot).as_bytes());
+        let mut ticket = u128::from_str_radix(&seed[..32], 16).unwrap_or(0) % total;
+        let mut picked = 0;
+        for (i, &stake) in self.stakes.iter().enumerate() {
+            if ticket < stake as u128 { picked = i; break; }
+            ticket -= stake as u128;
+        }
+        &self.validators[picked]
     }
 }
 
-pub fn verify_block_poa(cfg: &PoAConfig, parent_hash: &str, block: &Block) -> Result<(), String> {
-    let expected = cfg.expected_leader(block.height);
+pub fn verify_block_poa(cfg: &PoAConfig, chain_id: &str, parent: &Block, block: &Block) -> Result<(), String> {
+    if block.parent != parent.hash {
+        return Err(format!("bad parent: {} != {}", block.parent, parent.hash));
+    }
+    if block.slot <= parent.slot {
+        return Err(format!("slot {} not after parent slot {}", block.slot, parent.slot));
+    }
+    let expected = cfg.leader(parent, block.slot);
     if !block.validator_pk.eq_ignore_ascii_case(&expected.pk) {
-        return Err(format!("unexpected leader: got {}, expected {}", block.validator_pk, expected.pk));
+        return Err(format!("unexpected leader for slot {}: got {}, expected {}", block.slot, block.validator_pk, expected.pk));
     }
-    if block.parent != parent_hash {
-        return Err(format!("bad parent: {} != {}", block.parent, parent_hash));
+    let start = cfg.slot_start(block.slot);
+    if block.timestamp < start || block.timestamp >= start + cfg.slot_ms as u128 {
+        return Err(format!("timestamp {} outside slot {}", block.timestamp, block.slot));
+    }
+    if block.timestamp > now_ms() + cfg.max_drift_ms {
+        return Err(format!("timestamp {} is in the future", block.timestamp));
     }
     let sig = hex::decode(&block.validator_sig).map_err(|_| "bad leader sig hex".to_string())?;
     let pk  = hex::decode(&block.validator_pk).map_err(|_| "bad leader pk hex".to_string())?;
-    if !pq::verify_mldsa3(block.hash.as_bytes(), &sig, &pk) {
+    if !pq::verify_mldsa3(&SignedHeader::payload(chain_id, &block.hash), &sig, &pk) {
         return Err("invalid leader signature".into());
     }
     Ok(())
 }
+
+/// Whether `votes` out of a total voting power of `n` are more than 2/3.
+pub fn has_quorum(votes: u64, n: u64) -> bool {
+    n > 0 && votes as u128 * 3 > n as u128 * 2
+}
+
+/// Voting power of the distinct voters among `pks`, and of all `voters`.
+pub fn voting_power<'a>(voters: &[(String, u64)], pks: impl IntoIterator<Item = &'a str>) -> (u64, u64) {
+    let seen: std::collections::HashSet<String> = pks.into_iter().map(str::to_lowercase).collect();
+    let power = |(pk, w): &(String, u64)| if seen.contains(&pk.to_lowercase()) { *w } else { 0 };
+    (voters.iter().map(power).fold(0, u64::saturating_add), voters.iter().map(|(_, w)| *w).fold(0, u64::saturating_add))
+}
+
+/// Whether `url` can be dialled as a peer: an absolute http(s) URL with a
+/// host and no credentials, query or fragment.
+pub fn is_valid_url(url: &str) -> bool {
+    if url.len() > 256 { return false; }
+    reqwest::Url::parse(url).is_ok_and(|u| {
+        matches!(u.scheme(), "http" | "https") && u.host_str().is_some_and(|h| !h.is_empty())
+            && u.username().is_empty() && u.password().is_none() && u.query().is_none() && u.fragment().is_none()
+    })
+}
+
+pub fn verify_precommit(validators: &[(String, u64)], chain_id: &str, p: &Precommit) -> Result<(), String> {
+    if !validators.iter().any(|(v, _)| v.eq_ignore_ascii_case(&p.validator_pk)) {
+        return Err(format!("precommit from unknown validator {}", p.validator_pk));
+    }
+    let sig = hex::decode(&p.sig).map_err(|_| "bad precommit sig hex".to_string())?;
+    let pk  = hex::decode(&p.validator_pk).map_err(|_| "bad precommit pk hex".to_string())?;
+    if !pq::verify_mldsa3(&Precommit::payload(chain_id, p.height, &p.block_hash), &sig, &pk) {
+        return Err("invalid precommit signature".into());
+    }
+    Ok(())
+}
+
+/// Checks that every precommit is valid, votes for the certified block, and
+/// that distinct validators reach a quorum of voting power.
+pub fn verify_cert(validators: &[(String, u64)], chain_id: &str, cert: &FinalityCert) -> Result<(), String> {
+    for p in &cert.precommits {
+        if p.height != cert.height || p.block_hash != cert.block_hash {
+            return Err("precommit for a different block".into());
+        }
+        verify_precommit(validators, chain_id, p)?;
+    }
+    let (votes, total) = voting_power(validators, cert.precommits.iter().map(|p| p.validator_pk.as_str()));
+    if !has_quorum(votes, total) {
+        return Err(format!("voting power {} of {} is not a quorum", votes, total));
+    }
+    Ok(())
+}
+
+/// Checks that the header hashes to its `hash` and is signed by `validator_pk`
+/// for `chain_id`.
+pub fn verify_header_sig(h: &SignedHeader, chain_id: &str) -> Result<(), String> {
+    if h.hash != h.compute_hash() { return Err("header hash mismatch".into()); }
+    let sig = hex::decode(&h.validator_sig).map_err(|_| "bad leader sig hex".to_string())?;
+    let pk  = hex::decode(&h.validator_pk).map_err(|_| "bad leader pk hex".to_string())?;
+    if !pq::verify_mldsa3(&SignedHeader::payload(chain_id, &h.hash), &sig, &pk) {
+        return Err("invalid leader signature".into());
+    }
+    Ok(())
+}
+
+/// Evidence is valid when both headers are correctly signed on `chain_id` by
+/// the same validator for the same slot and differ. Leaders are chosen per
+/// slot, so two blocks at one height from different slots are not an offence.
+pub fn verify_evidence(e: &Evidence, chain_id: &str) -> Result<(), String> {
+    if !e.a.validator_pk.eq_ignore_ascii_case(&e.b.validator_pk) { return Err("headers from different validators".into()); }
+    if e.a.slot != e.b.slot { return Err("headers for different slots".into()); }
+    if e.a.hash == e.b.hash { return Err("headers are identical".into()); }
+    verify_header_sig(&e.a, chain_id)?;
+    verify_header_sig(&e.b, chain_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validator(i: usize, pk: &[u8]) -> Validator {
+        Validator { id: format!("v{}", i), url: format!("http://127.0.0.1:{}", 7000 + i), pk: hex::encode(pk) }
+    }
+
+    fn config(validators: Vec<Validator>, stakes: Vec<u64>) -> PoAConfig {
+        PoAConfig { peers: validators.clone(), validators, slot_ms: 1000, genesis_ms: 0, max_drift_ms: 500, stakes }
+    }
+
+    fn block(hash: &str, slot: u64) -> Block {
+        Block {
+            height: 0, slot, parent: String::new(), hash: hash.into(), timestamp: 0, tx_root: String::new(),
+            state_root: String::new(), receipts_root: String::new(), validator_pk: String::new(),
+            validator_sig: String::new(), gas_used: 0, txs: Vec::new(),
+        }
+    }
+
+    /// A child of `parent` for `slot`, stamped `timestamp` and signed by `sk`.
+    fn signed_block(sk: &[u8], pk: &[u8], parent: &Block, slot: u64, timestamp: u128) -> Block {
+        let mut b = block("", slot);
+        b.height = parent.height + 1;
+        b.parent = parent.hash.clone();
+        b.timestamp = timestamp;
+        b.validator_pk = hex::encode(pk);
+        b.hash = b.compute_hash();
+        b.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &b.hash), sk));
+        b
+    }
+
+    #[test]
+    fn blocks_must_come_from_the_slot_leader_within_the_slot() {
+        let keys: Vec<_> = (0..2).map(|_| pq::keygen_mldsa3()).collect();
+        let set: Vec<Validator> = keys.iter().enumerate().map(|(i, (_, pk))| validator(i, pk)).collect();
+        let cfg = config(set, Vec::new());
+        let parent = block("aa", 0);
+        let (sk0, pk0) = &keys[0];
+        let (sk1, pk1) = &keys[1];
+        // v1 leads odd slots, which start at their number in seconds.
+        verify_block_poa(&cfg, "test", &parent, &signed_block(sk1, pk1, &parent, 3, 3_500)).unwrap();
+
+        let err = verify_block_poa(&cfg, "test", &parent, &signed_block(sk0, pk0, &parent, 3, 3_500)).unwrap_err();
+        assert!(err.starts_with("unexpected leader for slot 3"), "{}", err);
+        let err = verify_block_poa(&cfg, "test", &parent, &signed_block(sk1, pk1, &parent, 3, 2_999)).unwrap_err();
+        assert_eq!(err, "timestamp 2999 outside slot 3");
+        let err = verify_block_poa(&cfg, "test", &parent, &signed_block(sk1, pk1, &parent, 3, 4_000)).unwrap_err();
+        assert_eq!(err, "timestamp 4000 outside slot 3");
+
+        // A slot that starts past the drift bound cannot be produced yet.
+        let slot = cfg.slot_at(now_ms() + 60_000) | 1;
+        let early = signed_block(sk1, pk1, &parent, slot, cfg.slot_start(slot));
+        assert!(verify_block_poa(&cfg, "test", &parent, &early).unwrap_err().ends_with("is in the future"));
+        // One that starts within it can.
+        let slot = cfg.slot_at(now_ms() + 200);
+        let (sk, pk) = &keys[(slot % 2) as usize];
+        let soon = signed_block(sk, pk, &parent, slot, cfg.slot_start(slot));
+        verify_block_poa(&cfg, "test", &parent, &soon).unwrap();
+    }
+
+    fn precommit(sk: &[u8], pk: &[u8], hash: &str) -> Precommit {
+        let sig = pq::sign_mldsa3(&Precommit::payload("test", 1, hash), sk);
+        Precommit { height: 1, block_hash: hash.into(), validator_pk: hex::encode(pk), sig: hex::encode(sig) }
+    }
+
+    #[test]
+    fn leader_is_drawn_by_stake() {
+        let set: Vec<Validator> = (0..2).map(|i| validator(i, &[i as u8])).collect();
+        let parent = block("aa", 0);
+        let cfg = config(set.clone(), vec![99, 1]);
+        let led_by_first = (1..=200).filter(|&s| cfg.leader(&parent, s).id == "v0").count();
+        assert!(led_by_first > 180, "v0 led {} of 200 slots", led_by_first);
+        // Only the seed picks the leader, so every node agrees.
+        assert_eq!(cfg.leader(&parent, 7).id, cfg.leader(&parent, 7).id);
+        // Without stake, nobody is ever drawn.
+        assert!((1..=200).all(|s| config(set.clone(), vec![5, 0]).leader(&parent, s).id == "v0"));
+        // Without stakes, or with stakes that do not line up, round robin applies.
+        assert_eq!(config(set.clone(), Vec::new()).leader(&parent, 3).id, "v1");
+        assert_eq!(config(set, vec![1]).leader(&parent, 4).id, "v0");
+    }
+
+    #[test]
+    fn many_small_stakes_cannot_outvote_a_large_one() {
+        let keys: Vec<_> = (0..11).map(|_| pq::keygen_mldsa3()).collect();
+        let set: Vec<Validator> = keys.iter().enumerate().map(|(i, (_, pk))| validator(i, pk)).collect();
+        let mut stakes = vec![1; 11];
+        stakes[0] = 100;
+        let voters = config(set, stakes).voters();
+        let cert = |signers: &[(Vec<u8>, Vec<u8>)]| FinalityCert {
+            height: 1, block_hash: "bb".into(),
+            precommits: signers.iter().map(|(sk, pk)| precommit(sk, pk, "bb")).collect(),
+        };
+        let err = verify_cert(&voters, "test", &cert(&keys[1..])).unwrap_err();
+        assert_eq!(err, "voting power 10 of 110 is not a quorum");
+        verify_cert(&voters, "test", &cert(&keys[..1])).unwrap();
+        // Counted by head, the ten would have been a quorum.
+        assert!(has_quorum(10, 11));
+    }
+
+    #[test]
+    fn only_dialable_urls_become_peers() {
+        for url in ["http://127.0.0.1:7001", "https://node.example/"] { assert!(is_valid_url(url), "{}", url); }
+        for url in ["", "node:7001", "ftp://node", "http://u:p@node", "http://node/?x=1", "http://node#f", "file:///etc/passwd"] {
+            assert!(!is_valid_url(url), "{}", url);
+        }
+        let mut cfg = config(Vec::new(), Vec::new());
+        let mut bad = validator(1, &[1]);
+        bad.url = "javascript:alert(1)".into();
+        cfg.set_validators(vec![validator(0, &[0]), bad]);
+        assert_eq!(cfg.validators.len(), 2);
+        assert_eq!(cfg.peers.iter().map(|p| p.id.as_str()).collect::<Vec<_>>(), ["v0"]);
+    }
+}
@@ -0,0 +1,130 @@
+use super::{staking, validators, Ctx, Contract, CtxResult, CtxError};
+use crate::consensus;
+use crate::pq;
+use crate::types::Evidence;
+use serde_json::{json, Value};
+
+/// Accepts proof that a validator signed two different blocks for one slot.
+/// A bonded validator is slashed and jailed in `staking`; a validator in the
+/// PoA set is removed from it. Each offence is punished once.
+pub struct EvidenceContract;
+
+const NS: &str = "evidence";
+
+impl Contract for EvidenceContract {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "submit" => {
+                let e: Evidence = serde_json::from_value(args.get("evidence").cloned().unwrap_or(Value::Null))
+                    .map_err(|_| CtxError::BadArgs("evidence {a,b}".into()))?;
+                consensus::verify_evidence(&e, ctx.chain_id()).map_err(|err| CtxError::Logic(format!("invalid evidence: {}", err)))?;
+                let pk = e.a.validator_pk.to_lowercase();
+                let addr = pq::address_from_pk(&hex::decode(&pk).unwrap_or_default());
+                let key = format!("{}:{}", addr, e.a.slot);
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&key)) {
+                    return Err(CtxError::Logic("evidence already processed".into()));
+                }
+
+                let mut out = json!({"ok": true, "event": "Equivocation", "validator": addr, "slot": e.a.slot, "height": e.a.height});
+                if staking::validator(ctx, &addr).is_some() {
+                    let (slashed, jailed_until) = staking::slash(ctx, &addr)?;
+                    out["slashed"] = json!(slashed);
+                    out["jailed_until"] = json!(jailed_until);
+                }
+                // Not an error when the validator was already removed from the PoA set.
+                if let Ok(from_height) = validators::jail(ctx, &pk) {
+                    out["removed_from_height"] = json!(from_height);
+                }
+                if out.get("slashed").is_none() && out.get("removed_from_height").is_none() {
+                    return Err(CtxError::Logic("not an active validator".into()));
+                }
+                let record = json!({"reporter": caller, "reported_at": ctx.height(), "evidence": e});
+                ctx.ns_mut(NS).insert(key, record);
+                Ok(out)
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "evidence" => {
+                let who = args.get("validator").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("validator".into()))?;
+                let slot = args.get("slot").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("slot".into()))?;
+                Ok(ctx.ns(NS).and_then(|m| m.get(&format!("{}:{}", who, slot))).cloned().unwrap_or(Value::Null))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_mut, dispatch_query, staking::Staking, token, validators::Validators};
+    use crate::types::SignedHeader;
+    use std::sync::Arc;
+
+    /// A header for `slot` signed by `sk`; `root` tells otherwise equal headers apart.
+    fn header(sk: &[u8], pk: &[u8], slot: u64, root: &str) -> SignedHeader {
+        let mut h = SignedHeader {
+            height: 5, slot, parent: "00".repeat(32), hash: String::new(), timestamp: 0, ntx: 0,
+            tx_root: String::new(), state_root: root.into(), receipts_root: String::new(),
+            validator_pk: hex::encode(pk), validator_sig: String::new(),
+        };
+        h.hash = h.compute_hash();
+        h.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &h.hash), sk));
+        h
+    }
+
+    /// A ctx on chain `test` where the validator behind `pk` bonded 50.
+    fn setup(pk: &[u8]) -> (Ctx, String) {
+        let validator = pq::address_from_pk(pk);
+        let mut ctx = testutil::ctx(vec![Arc::new(Staking), Arc::new(Validators), Arc::new(EvidenceContract)]);
+        ctx.set_chain_id("test");
+        ctx.set_height(10);
+        token::mint_to(&mut ctx, &validator, 100).unwrap();
+        dispatch_mut(&mut ctx, &validator, "staking", "bond", &json!({"amount": 50, "pk": hex::encode(pk), "url": "http://v"})).unwrap();
+        (ctx, validator)
+    }
+
+    fn submit(ctx: &mut Ctx, a: SignedHeader, b: SignedHeader) -> CtxResult {
+        dispatch_mut(ctx, &addr('r'), NS, "submit", &json!({"evidence": Evidence { a, b }}))
+    }
+
+    #[test]
+    fn double_sign_slashes_and_jails_once() {
+        let (sk, pk) = pq::keygen_mldsa3();
+        let (mut ctx, validator) = setup(&pk);
+        let out = submit(&mut ctx, header(&sk, &pk, 7, "a"), header(&sk, &pk, 7, "b")).unwrap();
+        assert_eq!(out["slashed"], json!(5));
+        assert_eq!(out["jailed_until"], json!(1010));
+        let v = staking::validator(&ctx, &validator).unwrap();
+        assert_eq!((v.self_bond, v.jailed_until), (45, 1010));
+        let record = dispatch_query(&ctx, NS, "evidence", &json!({"validator": validator, "slot": 7})).unwrap();
+        assert_eq!(record["reporter"], json!(addr('r')));
+
+        let err = submit(&mut ctx, header(&sk, &pk, 7, "a"), header(&sk, &pk, 7, "b")).unwrap_err();
+        assert_eq!(err.to_string(), "evidence already processed");
+        assert_eq!(staking::validator(&ctx, &validator).unwrap().self_bond, 45);
+    }
+
+    #[test]
+    fn headers_from_different_slots_or_keys_are_rejected() {
+        let (sk, pk) = pq::keygen_mldsa3();
+        let (other_sk, other_pk) = pq::keygen_mldsa3();
+        let (mut ctx, validator) = setup(&pk);
+        let err = submit(&mut ctx, header(&sk, &pk, 7, "a"), header(&sk, &pk, 8, "b")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid evidence: headers for different slots");
+        let err = submit(&mut ctx, header(&sk, &pk, 7, "a"), header(&other_sk, &other_pk, 7, "b")).unwrap_err();
+        assert_eq!(err.to_string(), "invalid evidence: headers from different validators");
+        // Signed for another chain.
+        let mut forged = header(&sk, &pk, 7, "b");
+        forged.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("other", &forged.hash), &sk));
+        assert!(submit(&mut ctx, header(&sk, &pk, 7, "a"), forged).is_err());
+        assert_eq!(staking::validator(&ctx, &validator).unwrap().self_bond, 50);
+    }
+}
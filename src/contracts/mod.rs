@@ -1,5 +1,6 @@
 use serde_json::Value;
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 pub type CtxResult = Result<Value, CtxError>;
@@ -9,42 +10,231 @@ pub enum CtxError {
     BadArgs(String),
     MethodNotFound,
     ContractNotFound,
+    OutOfGas,
     Logic(String),
 }
 
+impl std::fmt::Display for CtxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CtxError::BadArgs(s) => write!(f, "bad args: {}", s),
+            CtxError::MethodNotFound => write!(f, "method not found"),
+            CtxError::ContractNotFound => write!(f, "contract not found"),
+            CtxError::OutOfGas => write!(f, "out of gas"),
+            CtxError::Logic(s) => write!(f, "{}", s),
+        }
+    }
+}
+
 pub trait Contract: Send + Sync + 'static {
     fn name(&self) -> &'static str;
     fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult;
     fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult;
 }
 
-#[derive(Default)]
+/// Gas charged for each contract call, nested calls included.
+pub const GAS_CALL: u64 = 1_000;
+/// Gas charged for each `Ctx::ns` read.
+pub const GAS_READ: u64 = 10;
+/// Gas charged for each `Ctx::ns_mut` access.
+pub const GAS_WRITE: u64 = 100;
+
+type State = HashMap<String, HashMap<String, Value>>;
+
+/// A value as it was before a journaled write: (namespace, key, old value).
+type JournalEntry = (String, String, Option<Value>);
+
+#[derive(Default, Clone)]
 pub struct Ctx {
-    state: HashMap<String, HashMap<String, Value>>,
+    state: State,
     contracts: HashMap<&'static str, Arc<dyn Contract>>,
+    /// Height of the block being executed; not part of the state root.
+    height: u64,
+    /// Network this state belongs to; not part of the state root.
+    chain_id: String,
+    /// Gas used by the running tx; reads charge it through `&self`.
+    gas_used: Cell<u64>,
+    /// Limit of the running tx, `None` outside of tx execution.
+    gas_limit: Option<u64>,
+    /// Undo log of writes made while a checkpoint is open.
+    journal: Vec<JournalEntry>,
+    open_checkpoints: usize,
+}
+
+/// Position in the journal to revert to; checkpoints nest and must be
+/// committed or reverted innermost first.
+#[must_use]
+pub struct Checkpoint(usize);
+
+/// Write access to one namespace. Changes are journaled while a checkpoint
+/// is open so they can be undone.
+pub struct NsMut<'a> {
+    ns: &'a str,
+    map: &'a mut HashMap<String, Value>,
+    journal: Option<&'a mut Vec<JournalEntry>>,
+}
+
+impl NsMut<'_> {
+    pub fn get(&self, key: &str) -> Option<&Value> { self.map.get(key) }
+    pub fn contains_key(&self, key: &str) -> bool { self.map.contains_key(key) }
+    pub fn insert(&mut self, key: String, value: Value) -> Option<Value> {
+        let prev = self.map.insert(key.clone(), value);
+        if let Some(j) = self.journal.as_mut() { j.push((self.ns.into(), key, prev.clone())); }
+        prev
+    }
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let prev = self.map.remove(key);
+        if let (Some(j), Some(_)) = (self.journal.as_mut(), &prev) { j.push((self.ns.into(), key.into(), prev.clone())); }
+        prev
+    }
 }
 
 impl Ctx {
     pub fn register(&mut self, c: Arc<dyn Contract>) {
         self.contracts.insert(c.name(), c);
     }
-    pub fn ns_mut(&mut self, ns: &str) -> &mut HashMap<String, Value> {
-        self.state.entry(ns.into()).or_default()
+    pub fn ns_mut<'a>(&'a mut self, ns: &'a str) -> NsMut<'a> {
+        self.charge(GAS_WRITE);
+        NsMut {
+            ns,
+            map: self.state.entry(ns.into()).or_default(),
+            journal: (self.open_checkpoints > 0).then_some(&mut self.journal),
+        }
     }
     pub fn ns(&self, ns: &str) -> Option<&HashMap<String, Value>> {
+        self.charge(GAS_READ);
         self.state.get(ns)
     }
-    pub fn state_map(&self) -> &HashMap<String, HashMap<String, Value>> {
+    pub fn height(&self) -> u64 { self.height }
+    pub fn set_height(&mut self, height: u64) { self.height = height; }
+    pub fn chain_id(&self) -> &str { &self.chain_id }
+    pub fn set_chain_id(&mut self, chain_id: &str) { self.chain_id = chain_id.into(); }
+
+    /// Starts metering a tx that may use up to `limit` gas.
+    pub fn start_gas(&mut self, limit: u64) {
+        self.gas_used.set(0);
+        self.gas_limit = Some(limit);
+    }
+    /// Stops metering and returns the gas used, at most the limit.
+    pub fn stop_gas(&mut self) -> u64 {
+        let used = self.gas_used();
+        self.gas_limit = None;
+        self.gas_used.set(0);
+        used
+    }
+    pub fn gas_used(&self) -> u64 {
+        self.gas_used.get().min(self.gas_limit.unwrap_or(u64::MAX))
+    }
+    pub fn charge(&self, gas: u64) {
+        if self.gas_limit.is_some() {
+            self.gas_used.set(self.gas_used.get().saturating_add(gas));
+        }
+    }
+    pub fn out_of_gas(&self) -> bool {
+        self.gas_limit.is_some_and(|limit| self.gas_used.get() > limit)
+    }
+
+    /// Opens a checkpoint; writes from here on can be undone with `revert`.
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        self.open_checkpoints += 1;
+        Checkpoint(self.journal.len())
+    }
+    /// Keeps the writes since `cp`. They stay journaled while an outer
+    /// checkpoint is open, so reverting that one still undoes them.
+    pub fn commit(&mut self, cp: Checkpoint) {
+        self.open_checkpoints -= 1;
+        if self.open_checkpoints == 0 {
+            debug_assert_eq!(cp.0, 0);
+            self.journal.clear();
+        }
+    }
+    /// Undoes every write since `cp`, newest first.
+    pub fn revert(&mut self, cp: Checkpoint) {
+        self.open_checkpoints -= 1;
+        while self.journal.len() > cp.0 {
+            let (ns, key, prev) = self.journal.pop().unwrap();
+            let map = self.state.entry(ns).or_default();
+            match prev {
+                Some(v) => { map.insert(key, v); }
+                None => { map.remove(&key); }
+            }
+        }
+    }
+
+    pub fn state_map(&self) -> &State {
         &self.state
     }
+    /// Deterministic commitment to the whole state: namespaces and keys are
+    /// sorted, empty namespaces skipped and values serialized as canonical
+    /// (key-sorted) JSON.
+    pub fn state_root(&self) -> String {
+        let sorted: BTreeMap<&String, BTreeMap<&String, &Value>> = self.state.iter()
+            .filter(|(_, kv)| !kv.is_empty())
+            .map(|(ns, kv)| (ns, kv.iter().collect()))
+            .collect();
+        crate::util::hash_hex(&serde_json::to_vec(&sorted).unwrap())
+    }
 }
 
+// Helpers shared by the built-in contracts.
+
+fn add_u64(a: u64, b: u64) -> Result<u64, CtxError> {
+    a.checked_add(b).ok_or_else(|| CtxError::Logic("overflow".into()))
+}
+fn sub_u64(a: u64, b: u64) -> Result<u64, CtxError> {
+    a.checked_sub(b).ok_or_else(|| CtxError::Logic("underflow".into()))
+}
+fn is_valid_addr(s: &str) -> bool {
+    s.len() == 128 && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+/// Ids of 1 to `max_len` chars of `[A-Za-z0-9_-]`. They never contain `:`, so
+/// keys built from them cannot collide.
+fn is_valid_id(s: &str, max_len: usize) -> bool {
+    (1..=max_len).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Reads `key` of namespace `ns` as a `T`; `None` if missing or of another shape.
+fn get<T: serde::de::DeserializeOwned>(ctx: &Ctx, ns: &str, key: &str) -> Option<T> {
+    ctx.ns(ns).and_then(|m| m.get(key)).and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+fn put<T: serde::Serialize>(ctx: &mut Ctx, ns: &str, key: String, v: &T) {
+    ctx.ns_mut(ns).insert(key, serde_json::to_value(v).unwrap());
+}
+
+fn str_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, CtxError> {
+    args.get(name).and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs(name.into()))
+}
+fn addr_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, CtxError> {
+    let addr = str_arg(args, name)?;
+    if !is_valid_addr(addr) { return Err(CtxError::BadArgs(format!("{} (invalid addr)", name))); }
+    Ok(addr)
+}
+fn amount_arg(args: &Value) -> Result<u64, CtxError> {
+    let amount = args.get("amount").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("amount".into()))?;
+    if amount == 0 { return Err(CtxError::BadArgs("amount must be > 0".into())); }
+    Ok(amount)
+}
+
+/// Address that holds tokens on behalf of a built-in contract, e.g. bonded
+/// stake. Nobody has its key; only the contract itself moves funds from it.
+pub fn module_addr(name: &str) -> String {
+    crate::util::hash_hex(format!("module:{}", name).as_bytes())
+}
+
+/// Runs a state-changing call under its own checkpoint: its writes are kept
+/// only if it returns `Ok`, so a failed nested call leaves its caller's state
+/// as it was before the call. Reads and writes only add to the gas used, so
+/// the limit is checked before and after the call; a nested call that runs
+/// out fails its caller too.
 pub fn dispatch_mut(ctx: &mut Ctx, caller: &str, contract: &str, method: &str, args: &Value) -> CtxResult {
-    if let Some(c) = ctx.contracts.get(contract).cloned() {
-        c.call(ctx, caller, method, args)
-    } else {
-        Err(CtxError::ContractNotFound)
-    }
+    ctx.charge(GAS_CALL);
+    if ctx.out_of_gas() { return Err(CtxError::OutOfGas); }
+    let c = ctx.contracts.get(contract).cloned().ok_or(CtxError::ContractNotFound)?;
+    let cp = ctx.checkpoint();
+    let res = c.call(ctx, caller, method, args);
+    let res = if ctx.out_of_gas() { Err(CtxError::OutOfGas) } else { res };
+    if res.is_ok() { ctx.commit(cp) } else { ctx.revert(cp) }
+    res
 }
 pub fn dispatch_query(ctx: &Ctx, contract: &str, method: &str, args: &Value) -> CtxResult {
     if let Some(c) = ctx.contracts.get(contract).cloned() {
@@ -55,3 +245,35 @@ pub fn dispatch_query(ctx: &Ctx, contract: &str, method: &str, args: &Value) ->
 }
 
 pub mod token;
+pub mod tokens;
+pub mod nft;
+pub mod multisig;
+pub mod staking;
+pub mod evidence;
+pub mod validators;
+#[cfg(test)]
+pub(crate) mod testutil;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn state_root_ignores_insertion_order_and_empty_namespaces() {
+        let entries = [("token", "a", json!(1)), ("token", "b", json!({"y": 2, "x": 1})), ("nft", "c", json!("z"))];
+        let mut forward = Ctx::default();
+        for (ns, k, v) in &entries {
+            forward.ns_mut(ns).insert(k.to_string(), v.clone());
+        }
+        let mut backward = Ctx::default();
+        backward.ns_mut("unused");
+        for (ns, k, v) in entries.iter().rev() {
+            backward.ns_mut(ns).insert(k.to_string(), v.clone());
+        }
+        assert_eq!(forward.state_root(), backward.state_root());
+
+        backward.ns_mut("token").insert("a".into(), json!(2));
+        assert_ne!(forward.state_root(), backward.state_root());
+    }
+}
@@ -0,0 +1,239 @@
+use super::{dispatch_mut, get, put, str_arg, Ctx, Contract, CtxResult, CtxError};
+use crate::pq;
+use crate::util::hash_hex;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+/// M-of-N accounts controlled by ML-DSA-3 keys. Owners propose an inner call
+/// and approve it with their own signed txs; the approval that reaches the
+/// threshold runs the call with the multisig address as caller. If the inner
+/// call fails, that approval fails with it and the proposal stays pending.
+pub struct Multisig;
+
+#[derive(Serialize, Deserialize)]
+struct Account {
+    address: String,
+    /// Hex ML-DSA-3 public keys
+    owners: Vec<String>,
+    threshold: u64,
+    next_proposal: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct InnerCall {
+    contract: String,
+    method: String,
+    args: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Proposal {
+    id: u64,
+    call: InnerCall,
+    proposer: String,
+    approvals: Vec<String>,
+    executed: bool,
+    result: Option<Value>,
+}
+
+const NS: &str = "multisig";
+const MAX_OWNERS: usize = 32;
+
+fn account_key(addr: &str) -> String { format!("account:{}", addr) }
+fn proposal_key(addr: &str, id: u64) -> String { format!("proposal:{}:{}", addr, id) }
+
+fn account(ctx: &Ctx, args: &Value) -> Result<Account, CtxError> {
+    get(ctx, NS, &account_key(str_arg(args, "account")?)).ok_or_else(|| CtxError::Logic("unknown account".into()))
+}
+
+fn require_owner(a: &Account, caller: &str) -> Result<(), CtxError> {
+    let is_owner = a.owners.iter().any(|pk| hex::decode(pk).is_ok_and(|pk| pq::address_from_pk(&pk) == caller));
+    if is_owner { Ok(()) } else { Err(CtxError::Logic("caller is not an owner".into())) }
+}
+
+/// Runs `p` once it has `threshold` approvals; saves it either way.
+fn tally(ctx: &mut Ctx, a: &Account, mut p: Proposal) -> CtxResult {
+    let approvals = p.approvals.len() as u64;
+    if approvals < a.threshold {
+        put(ctx, NS, proposal_key(&a.address, p.id), &p);
+        return Ok(json!({"ok": true, "event": "MultisigApproved", "account": a.address, "id": p.id, "approvals": approvals, "threshold": a.threshold}));
+    }
+    let result = dispatch_mut(ctx, &a.address, &p.call.contract, &p.call.method, &p.call.args)?;
+    p.executed = true;
+    p.result = Some(result.clone());
+    put(ctx, NS, proposal_key(&a.address, p.id), &p);
+    Ok(json!({"ok": true, "event": "MultisigExecuted", "account": a.address, "id": p.id, "result": result}))
+}
+
+impl Contract for Multisig {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            // Anyone may create an account. Its address commits to the creator,
+            // owners, threshold and an optional `salt`, so it does not depend
+            // on what else was created before it.
+            "create" => {
+                let raw = args.get("owners").and_then(|v| v.as_array()).ok_or_else(|| CtxError::BadArgs("owners".into()))?;
+                if raw.len() > MAX_OWNERS { return Err(CtxError::BadArgs(format!("owners (at most {})", MAX_OWNERS))); }
+                let mut owners: Vec<String> = raw
+                    .iter()
+                    .map(|v| v.as_str().map(str::to_lowercase).ok_or_else(|| CtxError::BadArgs("owners (hex pks)".into())))
+                    .collect::<Result<_, _>>()?;
+                owners.sort();
+                owners.dedup();
+                if owners.iter().any(|pk| !hex::decode(pk).is_ok_and(|pk| pq::is_mldsa3_pk(&pk))) {
+                    return Err(CtxError::BadArgs("owners (invalid ML-DSA-3 pk)".into()));
+                }
+                let threshold = args.get("threshold").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("threshold".into()))?;
+                if threshold == 0 || threshold > owners.len() as u64 {
+                    return Err(CtxError::BadArgs("threshold must be between 1 and the number of owners".into()));
+                }
+                let salt = match args.get("salt") {
+                    None | Some(Value::Null) => "",
+                    Some(_) => str_arg(args, "salt")?,
+                };
+                if salt.len() > 64 { return Err(CtxError::BadArgs("salt (at most 64 chars)".into())); }
+                let address = hash_hex(format!("multisig:{}:{}:{}:{}", caller, owners.join(","), threshold, salt).as_bytes());
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&account_key(&address))) {
+                    return Err(CtxError::Logic("account already exists; use another salt".into()));
+                }
+                put(ctx, NS, account_key(&address), &Account { address: address.clone(), owners, threshold, next_proposal: 0 });
+                Ok(json!({"ok": true, "event": "MultisigCreated", "account": address, "creator": caller, "threshold": threshold}))
+            }
+            // The proposer's approval is counted right away.
+            "propose" => {
+                let mut a = account(ctx, args)?;
+                require_owner(&a, caller)?;
+                let call: InnerCall = serde_json::from_value(args.get("call").cloned().unwrap_or(Value::Null))
+                    .map_err(|_| CtxError::BadArgs("call {contract,method,args}".into()))?;
+                let p = Proposal { id: a.next_proposal, call, proposer: caller.into(), approvals: vec![caller.into()], executed: false, result: None };
+                a.next_proposal += 1;
+                put(ctx, NS, account_key(&a.address), &a);
+                tally(ctx, &a, p)
+            }
+            "approve" => {
+                let a = account(ctx, args)?;
+                require_owner(&a, caller)?;
+                let id = args.get("id").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("id".into()))?;
+                let mut p: Proposal = get(ctx, NS, &proposal_key(&a.address, id)).ok_or_else(|| CtxError::Logic("unknown proposal".into()))?;
+                if p.executed { return Err(CtxError::Logic("proposal already executed".into())); }
+                if p.approvals.iter().any(|x| x == caller) { return Err(CtxError::Logic("already approved".into())); }
+                p.approvals.push(caller.into());
+                tally(ctx, &a, p)
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "account" => Ok(json!(account(ctx, args)?)),
+            "proposal" => {
+                let a = account(ctx, args)?;
+                let id = args.get("id").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("id".into()))?;
+                get::<Value>(ctx, NS, &proposal_key(&a.address, id)).ok_or_else(|| CtxError::Logic("unknown proposal".into()))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_query, token};
+    use std::sync::Arc;
+
+    /// Owner keys as (hex pk, address).
+    fn owners(n: usize) -> Vec<(String, String)> {
+        (0..n).map(|_| {
+            let (_, pk) = pq::keygen_mldsa3();
+            (hex::encode(&pk), pq::address_from_pk(&pk))
+        }).collect()
+    }
+
+    /// Creates an account of `owners` holding 100 token.
+    fn create(ctx: &mut Ctx, owners: &[(String, String)], threshold: u64) -> String {
+        let pks: Vec<&String> = owners.iter().map(|(pk, _)| pk).collect();
+        let out = dispatch_mut(ctx, &addr('a'), NS, "create", &json!({"owners": pks, "threshold": threshold})).unwrap();
+        let account = out["account"].as_str().unwrap().to_string();
+        token::mint_to(ctx, &account, 100).unwrap();
+        account
+    }
+
+    fn transfer(to: &str, amount: u64) -> Value {
+        json!({"contract": "token", "method": "transfer", "args": {"to": to, "amount": amount}})
+    }
+
+    fn balance(ctx: &Ctx, who: &str) -> Value {
+        dispatch_query(ctx, "token", "balance_of", &json!({"who": who})).unwrap()
+    }
+
+    #[test]
+    fn threshold_one_executes_on_propose_as_the_account() {
+        let mut ctx = testutil::ctx(vec![Arc::new(Multisig)]);
+        let o = owners(1);
+        let account = create(&mut ctx, &o, 1);
+        let out = dispatch_mut(&mut ctx, &o[0].1, NS, "propose", &json!({"account": account, "call": transfer(&addr('c'), 30)})).unwrap();
+        assert_eq!(out["event"], "MultisigExecuted");
+        // The owner holds nothing; the tokens came from the account itself.
+        assert_eq!(balance(&ctx, &o[0].1), json!(0));
+        assert_eq!(balance(&ctx, &account), json!(70));
+        assert_eq!(balance(&ctx, &addr('c')), json!(30));
+    }
+
+    #[test]
+    fn non_owner_and_double_approval_are_rejected() {
+        let mut ctx = testutil::ctx(vec![Arc::new(Multisig)]);
+        let o = owners(3);
+        let account = create(&mut ctx, &o, 2);
+        let err = dispatch_mut(&mut ctx, &addr('b'), NS, "propose", &json!({"account": account, "call": transfer(&addr('b'), 1)})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not an owner");
+
+        dispatch_mut(&mut ctx, &o[0].1, NS, "propose", &json!({"account": account, "call": transfer(&addr('c'), 1)})).unwrap();
+        let err = dispatch_mut(&mut ctx, &addr('b'), NS, "approve", &json!({"account": account, "id": 0})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not an owner");
+        let err = dispatch_mut(&mut ctx, &o[0].1, NS, "approve", &json!({"account": account, "id": 0})).unwrap_err();
+        assert_eq!(err.to_string(), "already approved");
+        assert_eq!(balance(&ctx, &addr('c')), json!(0));
+    }
+
+    #[test]
+    fn failed_inner_call_leaves_proposal_pending() {
+        let mut ctx = testutil::ctx(vec![Arc::new(Multisig)]);
+        let o = owners(2);
+        let account = create(&mut ctx, &o, 2);
+        dispatch_mut(&mut ctx, &o[0].1, NS, "propose", &json!({"account": account, "call": transfer(&addr('c'), 150)})).unwrap();
+
+        assert!(dispatch_mut(&mut ctx, &o[1].1, NS, "approve", &json!({"account": account, "id": 0})).is_err());
+        let p = dispatch_query(&ctx, NS, "proposal", &json!({"account": account, "id": 0})).unwrap();
+        assert_eq!((p["approvals"].as_array().unwrap().len(), &p["executed"]), (1, &json!(false)));
+
+        token::mint_to(&mut ctx, &account, 50).unwrap();
+        let out = dispatch_mut(&mut ctx, &o[1].1, NS, "approve", &json!({"account": account, "id": 0})).unwrap();
+        assert_eq!(out["event"], "MultisigExecuted");
+        assert_eq!(balance(&ctx, &addr('c')), json!(150));
+    }
+
+    #[test]
+    fn address_commits_to_creator_owners_and_salt() {
+        let mut ctx = testutil::ctx(vec![Arc::new(Multisig)]);
+        let o = owners(2);
+        let pks: Vec<&String> = o.iter().map(|(pk, _)| pk).collect();
+        let args = json!({"owners": pks, "threshold": 1});
+        let first = dispatch_mut(&mut ctx, &addr('a'), NS, "create", &args).unwrap()["account"].clone();
+
+        let err = dispatch_mut(&mut ctx, &addr('a'), NS, "create", &args).unwrap_err();
+        assert!(matches!(err, CtxError::Logic(_)), "{}", err);
+        let other_creator = dispatch_mut(&mut ctx, &addr('b'), NS, "create", &args).unwrap()["account"].clone();
+        let salted = dispatch_mut(&mut ctx, &addr('a'), NS, "create", &json!({"owners": pks, "threshold": 1, "salt": "2"})).unwrap()["account"].clone();
+        assert_ne!(first, other_creator);
+        assert_ne!(first, salted);
+
+        let too_many = vec![o[0].0.clone(); MAX_OWNERS + 1];
+        let err = dispatch_mut(&mut ctx, &addr('a'), NS, "create", &json!({"owners": too_many, "threshold": 1})).unwrap_err();
+        assert!(matches!(err, CtxError::BadArgs(_)), "{}", err);
+    }
+}
@@ -0,0 +1,246 @@
+use super::{addr_arg, get, is_valid_id, put, str_arg, Ctx, Contract, CtxResult, CtxError};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+/// Non-fungible tokens grouped in collections. The creator of a collection
+/// mints into it; each token has one owner, an optional approved spender and
+/// a metadata URI.
+pub struct Nft;
+
+#[derive(Serialize, Deserialize)]
+struct Collection {
+    id: String,
+    name: String,
+    symbol: String,
+    creator: String,
+    /// Tokens currently in existence
+    supply: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NftToken {
+    owner: String,
+    uri: String,
+    approved: Option<String>,
+}
+
+const NS: &str = "nft";
+const MAX_PAGE: usize = 100;
+const MAX_ID_LEN: usize = 64;
+
+fn collection_key(id: &str) -> String { format!("collection:{}", id) }
+fn token_key(collection: &str, token_id: &str) -> String { format!("token:{}:{}", collection, token_id) }
+/// Index entry listing `token_id` among the tokens of `owner`.
+fn owned_key(owner: &str, collection: &str, token_id: &str) -> String { format!("owned:{}:{}:{}", owner, collection, token_id) }
+/// Left behind by `burn` so that a token id is never minted twice.
+fn burned_key(collection: &str, token_id: &str) -> String { format!("burned:{}:{}", collection, token_id) }
+
+fn id_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, CtxError> {
+    let id = str_arg(args, name)?;
+    if !is_valid_id(id, MAX_ID_LEN) { return Err(CtxError::BadArgs(format!("{} (1-{} chars of [A-Za-z0-9_-])", name, MAX_ID_LEN))); }
+    Ok(id)
+}
+
+fn collection(ctx: &Ctx, id: &str) -> Result<Collection, CtxError> {
+    get(ctx, NS, &collection_key(id)).ok_or_else(|| CtxError::Logic("unknown collection".into()))
+}
+
+/// The token named by `collection` and `token_id` in `args`.
+fn token(ctx: &Ctx, args: &Value) -> Result<(String, String, NftToken), CtxError> {
+    let c = str_arg(args, "collection")?;
+    let id = str_arg(args, "token_id")?;
+    let t = get(ctx, NS, &token_key(c, id)).ok_or_else(|| CtxError::Logic("unknown token".into()))?;
+    Ok((c.into(), id.into(), t))
+}
+
+fn require_owner_or_approved(t: &NftToken, caller: &str) -> Result<(), CtxError> {
+    if t.owner == caller || t.approved.as_deref() == Some(caller) { Ok(()) }
+    else { Err(CtxError::Logic("caller is not the owner or approved".into())) }
+}
+
+impl Contract for Nft {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "create_collection" => {
+                let id = id_arg(args, "id")?;
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&collection_key(id))) {
+                    return Err(CtxError::Logic("collection already exists".into()));
+                }
+                let c = Collection {
+                    id: id.into(),
+                    name: str_arg(args, "name")?.into(),
+                    symbol: str_arg(args, "symbol")?.into(),
+                    creator: caller.into(),
+                    supply: 0,
+                };
+                put(ctx, NS, collection_key(id), &c);
+                Ok(json!({"ok": true, "event": "CollectionCreated", "id": id, "creator": caller}))
+            }
+            // Only the collection's creator mints.
+            "mint" => {
+                let mut c = collection(ctx, str_arg(args, "collection")?)?;
+                if c.creator != caller { return Err(CtxError::Logic("caller is not the collection creator".into())); }
+                let token_id = id_arg(args, "token_id")?;
+                let to = addr_arg(args, "to")?;
+                let uri = str_arg(args, "uri")?;
+                if uri.is_empty() || uri.len() > 512 { return Err(CtxError::BadArgs("uri (1-512 chars)".into())); }
+                let key = token_key(&c.id, token_id);
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&key)) {
+                    return Err(CtxError::Logic("token already exists".into()));
+                }
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&burned_key(&c.id, token_id))) {
+                    return Err(CtxError::Logic("token was burned".into()));
+                }
+                c.supply += 1;
+                put(ctx, NS, key, &NftToken { owner: to.into(), uri: uri.into(), approved: None });
+                put(ctx, NS, owned_key(to, &c.id, token_id), &true);
+                put(ctx, NS, collection_key(&c.id), &c);
+                Ok(json!({"ok": true, "event": "Mint", "collection": c.id, "token_id": token_id, "to": to, "uri": uri}))
+            }
+            // Moves the token and clears its approval.
+            "transfer" => {
+                let (c, id, mut t) = token(ctx, args)?;
+                require_owner_or_approved(&t, caller)?;
+                let to = addr_arg(args, "to")?;
+                if to == t.owner { return Err(CtxError::Logic("self-transfer not allowed".into())); }
+                let from = std::mem::replace(&mut t.owner, to.into());
+                t.approved = None;
+                ctx.ns_mut(NS).remove(&owned_key(&from, &c, &id));
+                put(ctx, NS, owned_key(to, &c, &id), &true);
+                put(ctx, NS, token_key(&c, &id), &t);
+                Ok(json!({"ok": true, "event": "Transfer", "collection": c, "token_id": id, "from": from, "to": to}))
+            }
+            // Lets `spender` transfer or burn the token; without `spender`, revokes.
+            "approve" => {
+                let (c, id, mut t) = token(ctx, args)?;
+                if t.owner != caller { return Err(CtxError::Logic("caller is not the owner".into())); }
+                t.approved = match args.get("spender") {
+                    None | Some(Value::Null) => None,
+                    Some(_) => Some(addr_arg(args, "spender")?.into()),
+                };
+                put(ctx, NS, token_key(&c, &id), &t);
+                Ok(json!({"ok": true, "event": "Approval", "collection": c, "token_id": id, "owner": caller, "spender": t.approved}))
+            }
+            // The id stays taken: it cannot be minted again.
+            "burn" => {
+                let (c, id, t) = token(ctx, args)?;
+                require_owner_or_approved(&t, caller)?;
+                let mut col = collection(ctx, &c)?;
+                col.supply -= 1;
+                let mut ns = ctx.ns_mut(NS);
+                ns.remove(&token_key(&c, &id));
+                ns.remove(&owned_key(&t.owner, &c, &id));
+                put(ctx, NS, burned_key(&c, &id), &true);
+                put(ctx, NS, collection_key(&c), &col);
+                Ok(json!({"ok": true, "event": "Burn", "collection": c, "token_id": id, "owner": t.owner}))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "collection" => Ok(json!(collection(ctx, str_arg(args, "id")?)?)),
+            "owner_of" => Ok(json!(token(ctx, args)?.2.owner)),
+            "token" => {
+                let (c, id, t) = token(ctx, args)?;
+                Ok(json!({"collection": c, "token_id": id, "owner": t.owner, "uri": t.uri, "approved": t.approved}))
+            }
+            // Page of `limit` (default and max 100) tokens from `offset`, in key order.
+            "tokens_of" => {
+                let prefix = format!("owned:{}:", str_arg(args, "owner")?);
+                let offset = args.get("offset").and_then(|v| v.as_u64()).unwrap_or(0) as usize;
+                let limit = args.get("limit").and_then(|v| v.as_u64()).map_or(MAX_PAGE, |l| (l as usize).min(MAX_PAGE));
+                let mut owned: Vec<(&str, &str)> = ctx.ns(NS).into_iter().flatten()
+                    .filter_map(|(k, _)| k.strip_prefix(&prefix)?.split_once(':'))
+                    .collect();
+                owned.sort();
+                let page: Vec<Value> = owned.iter().skip(offset).take(limit)
+                    .map(|(c, id)| json!({"collection": c, "token_id": id}))
+                    .collect();
+                Ok(json!({"total": owned.len(), "offset": offset, "tokens": page}))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_mut, dispatch_query};
+    use std::sync::Arc;
+
+    /// A ctx with collection `art` created by `a`.
+    fn ctx() -> Ctx {
+        let mut ctx = testutil::ctx(vec![Arc::new(Nft)]);
+        dispatch_mut(&mut ctx, &addr('a'), NS, "create_collection", &json!({"id": "art", "name": "Art", "symbol": "ART"})).unwrap();
+        ctx
+    }
+
+    fn mint(ctx: &mut Ctx, caller: &str, token_id: &str, to: &str) -> CtxResult {
+        dispatch_mut(ctx, caller, NS, "mint", &json!({"collection": "art", "token_id": token_id, "to": to, "uri": format!("ipfs://{}", token_id)}))
+    }
+
+    fn query(ctx: &Ctx, method: &str, args: Value) -> Value {
+        dispatch_query(ctx, NS, method, &args).unwrap()
+    }
+
+    #[test]
+    fn only_creator_mints() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = ctx();
+        let err = mint(&mut ctx, &b, "1", &b).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not the collection creator");
+        mint(&mut ctx, &a, "1", &b).unwrap();
+        assert_eq!(query(&ctx, "owner_of", json!({"collection": "art", "token_id": "1"})), json!(b));
+        assert_eq!(mint(&mut ctx, &a, "1", &a).unwrap_err().to_string(), "token already exists");
+    }
+
+    #[test]
+    fn transfer_by_approved_spender_clears_approval() {
+        let (a, b, c) = (addr('a'), addr('b'), addr('c'));
+        let mut ctx = ctx();
+        mint(&mut ctx, &a, "1", &a).unwrap();
+        dispatch_mut(&mut ctx, &a, NS, "approve", &json!({"collection": "art", "token_id": "1", "spender": b})).unwrap();
+        dispatch_mut(&mut ctx, &b, NS, "transfer", &json!({"collection": "art", "token_id": "1", "to": c})).unwrap();
+
+        let t = query(&ctx, "token", json!({"collection": "art", "token_id": "1"}));
+        assert_eq!((&t["owner"], &t["approved"]), (&json!(c), &Value::Null));
+        let err = dispatch_mut(&mut ctx, &b, NS, "transfer", &json!({"collection": "art", "token_id": "1", "to": b})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not the owner or approved");
+    }
+
+    #[test]
+    fn tokens_of_pages_in_key_order() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = ctx();
+        for id in ["1", "2", "3", "4", "5"] { mint(&mut ctx, &a, id, &b).unwrap(); }
+        mint(&mut ctx, &a, "6", &a).unwrap();
+
+        let page = query(&ctx, "tokens_of", json!({"owner": b, "offset": 1, "limit": 2}));
+        assert_eq!(page["total"], json!(5));
+        let ids: Vec<&Value> = page["tokens"].as_array().unwrap().iter().map(|t| &t["token_id"]).collect();
+        assert_eq!(ids, [&json!("2"), &json!("3")]);
+        let last = query(&ctx, "tokens_of", json!({"owner": b, "offset": 4}));
+        assert_eq!(last["tokens"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn burn_updates_supply_and_index_and_retires_the_id() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = ctx();
+        mint(&mut ctx, &a, "1", &b).unwrap();
+        mint(&mut ctx, &a, "2", &b).unwrap();
+        dispatch_mut(&mut ctx, &b, NS, "burn", &json!({"collection": "art", "token_id": "1"})).unwrap();
+
+        assert_eq!(query(&ctx, "collection", json!({"id": "art"}))["supply"], json!(1));
+        let owned = query(&ctx, "tokens_of", json!({"owner": b}));
+        assert_eq!((&owned["total"], &owned["tokens"][0]["token_id"]), (&json!(1), &json!("2")));
+        assert!(dispatch_query(&ctx, NS, "owner_of", &json!({"collection": "art", "token_id": "1"})).is_err());
+        assert_eq!(mint(&mut ctx, &a, "1", &a).unwrap_err().to_string(), "token was burned");
+    }
+}
@@ -0,0 +1,281 @@
+use super::{add_u64, amount_arg, dispatch_mut, get, module_addr, put, Ctx, Contract, CtxResult, CtxError};
+use crate::consensus::{self, Validator};
+use crate::pq;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+/// Bonded `token` balances backing PoS validators. Bonded tokens are held by
+/// the staking module address; unbonded tokens can be withdrawn after
+/// `QSC_UNBONDING_BLOCKS` blocks.
+pub struct Staking;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StakeValidator {
+    pub id: String,
+    pub url: String,
+    pub pk: String,
+    pub self_bond: u64,
+    pub delegated: u64,
+    /// No leader slots before this height; set when slashed.
+    #[serde(default)]
+    pub jailed_until: u64,
+}
+
+impl StakeValidator {
+    pub fn stake(&self) -> u64 { self.self_bond.saturating_add(self.delegated) }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Unbonding {
+    amount: u64,
+    release_height: u64,
+}
+
+const NS: &str = "staking";
+
+fn sub_stake(a: u64, b: u64) -> Result<u64, CtxError> {
+    a.checked_sub(b).ok_or_else(|| CtxError::Logic("insufficient stake".into()))
+}
+
+fn unbonding_blocks() -> u64 {
+    std::env::var("QSC_UNBONDING_BLOCKS").ok().and_then(|s| s.parse().ok()).unwrap_or(100)
+}
+
+fn validator_key(addr: &str) -> String { format!("validator:{}", addr) }
+fn delegation_key(delegator: &str, validator: &str) -> String { format!("delegation:{}:{}", delegator, validator) }
+fn unbonding_key(addr: &str) -> String { format!("unbonding:{}", addr) }
+
+fn adjust_total(ctx: &mut Ctx, add: u64, sub: u64) -> Result<(), CtxError> {
+    let total = get::<u64>(ctx, NS, "total_bonded").unwrap_or(0);
+    put(ctx, NS, "total_bonded".into(), &sub_stake(add_u64(total, add)?, sub)?);
+    Ok(())
+}
+
+/// Starts the unbonding period for `amount` owed back to `addr`.
+fn queue_unbonding(ctx: &mut Ctx, addr: &str, amount: u64) -> Result<u64, CtxError> {
+    let release_height = add_u64(ctx.height(), unbonding_blocks())?;
+    let mut queue: Vec<Unbonding> = get(ctx, NS, &unbonding_key(addr)).unwrap_or_default();
+    queue.push(Unbonding { amount, release_height });
+    put(ctx, NS, unbonding_key(addr), &queue);
+    adjust_total(ctx, 0, amount)?;
+    Ok(release_height)
+}
+
+pub fn validator(ctx: &Ctx, addr: &str) -> Option<StakeValidator> {
+    get(ctx, NS, &validator_key(addr))
+}
+
+/// Every registered validator with its address, in address order.
+pub fn validators(ctx: &Ctx) -> Vec<(String, StakeValidator)> {
+    let mut out: Vec<(String, StakeValidator)> = ctx.ns(NS).into_iter().flatten()
+        .filter_map(|(k, v)| {
+            let addr = k.strip_prefix("validator:")?;
+            Some((addr.to_string(), serde_json::from_value(v.clone()).ok()?))
+        })
+        .collect();
+    out.sort_by(|a, b| a.0.cmp(&b.0));
+    out
+}
+
+/// Stake a validator needs to lead, vote and be dialled as a peer
+/// (`QSC_MIN_STAKE`).
+fn min_stake() -> u64 {
+    std::env::var("QSC_MIN_STAKE").ok().and_then(|s| s.parse().ok()).unwrap_or(1000).max(1)
+}
+
+/// Unjailed validators with at least `min_stake`, in address order, for
+/// stake-weighted leader selection and finality.
+pub fn active_set(ctx: &Ctx) -> Vec<(Validator, u64)> {
+    let min = min_stake();
+    validators(ctx).into_iter()
+        .filter(|(_, v)| v.stake() >= min && v.jailed_until <= ctx.height())
+        .map(|(_, v)| (Validator { id: v.id.clone(), url: v.url.clone(), pk: v.pk.clone() }, v.stake()))
+        .collect()
+}
+
+/// Burns `QSC_SLASH_PERCENT` (default 10) of the validator's own bond and
+/// jails it for `QSC_JAIL_BLOCKS` (default 1000). The burned tokens stay
+/// locked in the module address. Returns the amount slashed and the height
+/// the jail ends.
+pub fn slash(ctx: &mut Ctx, addr: &str) -> Result<(u64, u64), CtxError> {
+    let percent: u64 = std::env::var("QSC_SLASH_PERCENT").ok().and_then(|s| s.parse().ok()).unwrap_or(10).min(100);
+    let jail: u64 = std::env::var("QSC_JAIL_BLOCKS").ok().and_then(|s| s.parse().ok()).unwrap_or(1000);
+    let mut v = validator(ctx, addr).ok_or_else(|| CtxError::Logic("unknown validator".into()))?;
+    let slashed = (v.self_bond as u128 * percent as u128 / 100) as u64;
+    v.self_bond -= slashed;
+    v.jailed_until = v.jailed_until.max(add_u64(ctx.height(), jail)?);
+    let jailed_until = v.jailed_until;
+    put(ctx, NS, validator_key(addr), &v);
+    adjust_total(ctx, 0, slashed)?;
+    Ok((slashed, jailed_until))
+}
+
+impl Contract for Staking {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            // Self-bond; the first bond registers the caller as a validator.
+            "bond" => {
+                let amount = amount_arg(args)?;
+                let mut v = match validator(ctx, caller) {
+                    Some(v) => v,
+                    None => {
+                        let pk = args.get("pk").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("pk".into()))?;
+                        let pk_bytes = hex::decode(pk).map_err(|_| CtxError::BadArgs("pk (invalid hex)".into()))?;
+                        if pq::address_from_pk(&pk_bytes) != caller {
+                            return Err(CtxError::BadArgs("pk does not match caller".into()));
+                        }
+                        let id = args.get("id").and_then(|v| v.as_str()).unwrap_or(caller).to_string();
+                        let url = args.get("url").and_then(|v| v.as_str())
+                            .filter(|u| consensus::is_valid_url(u))
+                            .ok_or_else(|| CtxError::BadArgs("url (http(s)://host[:port])".into()))?;
+                        StakeValidator { id, url: url.into(), pk: pk.into(), self_bond: 0, delegated: 0, jailed_until: 0 }
+                    }
+                };
+                v.self_bond = add_u64(v.self_bond, amount)?;
+                dispatch_mut(ctx, caller, "token", "transfer", &json!({"to": module_addr(NS), "amount": amount}))?;
+                put(ctx, NS, validator_key(caller), &v);
+                adjust_total(ctx, amount, 0)?;
+                Ok(json!({"ok": true, "event": "Bond", "validator": caller, "amount": amount, "stake": v.stake()}))
+            }
+            "delegate" => {
+                let amount = amount_arg(args)?;
+                let to = args.get("validator").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("validator".into()))?;
+                // `unbond` treats the caller's own validator as its self-bond.
+                if to == caller { return Err(CtxError::Logic("cannot delegate to yourself; use bond".into())); }
+                let mut v = validator(ctx, to).ok_or_else(|| CtxError::Logic("unknown validator".into()))?;
+                v.delegated = add_u64(v.delegated, amount)?;
+                let key = delegation_key(caller, to);
+                let delegation = add_u64(get::<u64>(ctx, NS, &key).unwrap_or(0), amount)?;
+                dispatch_mut(ctx, caller, "token", "transfer", &json!({"to": module_addr(NS), "amount": amount}))?;
+                put(ctx, NS, key, &delegation);
+                put(ctx, NS, validator_key(to), &v);
+                adjust_total(ctx, amount, 0)?;
+                Ok(json!({"ok": true, "event": "Delegate", "delegator": caller, "validator": to, "amount": amount}))
+            }
+            // Without `validator`, reduces the caller's own bond; with it, the
+            // caller's delegation to that validator.
+            "unbond" => {
+                let amount = amount_arg(args)?;
+                let target = args.get("validator").and_then(|v| v.as_str()).unwrap_or(caller).to_string();
+                let mut v = validator(ctx, &target).ok_or_else(|| CtxError::Logic("unknown validator".into()))?;
+                if target == caller {
+                    v.self_bond = sub_stake(v.self_bond, amount)?;
+                } else {
+                    let key = delegation_key(caller, &target);
+                    let left = sub_stake(get::<u64>(ctx, NS, &key).unwrap_or(0), amount)?;
+                    v.delegated = sub_stake(v.delegated, amount)?;
+                    if left == 0 { ctx.ns_mut(NS).remove(&key); } else { put(ctx, NS, key, &left); }
+                }
+                put(ctx, NS, validator_key(&target), &v);
+                let release_height = queue_unbonding(ctx, caller, amount)?;
+                Ok(json!({"ok": true, "event": "Unbond", "owner": caller, "validator": target, "amount": amount, "release_height": release_height}))
+            }
+            // Pays out every unbonding entry whose period has passed.
+            "withdraw" => {
+                let queue: Vec<Unbonding> = get(ctx, NS, &unbonding_key(caller)).unwrap_or_default();
+                let (ready, pending): (Vec<Unbonding>, Vec<Unbonding>) =
+                    queue.into_iter().partition(|u| u.release_height <= ctx.height());
+                let amount = ready.iter().try_fold(0u64, |acc, u| add_u64(acc, u.amount))?;
+                if amount == 0 { return Err(CtxError::Logic("nothing to withdraw".into())); }
+                dispatch_mut(ctx, &module_addr(NS), "token", "transfer", &json!({"to": caller, "amount": amount}))?;
+                if pending.is_empty() { ctx.ns_mut(NS).remove(&unbonding_key(caller)); } else { put(ctx, NS, unbonding_key(caller), &pending); }
+                Ok(json!({"ok": true, "event": "Withdraw", "to": caller, "amount": amount}))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        let addr_arg = |name: &str| args.get(name).and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs(name.into()));
+        match method {
+            "validators" => Ok(json!(validators(ctx).into_iter()
+                .map(|(addr, v)| json!({"address": addr, "id": v.id, "url": v.url, "pk": v.pk, "self_bond": v.self_bond, "delegated": v.delegated, "stake": v.stake(), "jailed_until": v.jailed_until}))
+                .collect::<Vec<_>>())),
+            "validator" => validator(ctx, addr_arg("who")?).map(|v| json!(v)).ok_or_else(|| CtxError::Logic("unknown validator".into())),
+            "delegation" => Ok(json!(get::<u64>(ctx, NS, &delegation_key(addr_arg("delegator")?, addr_arg("validator")?)).unwrap_or(0))),
+            "unbonding" => Ok(get::<Value>(ctx, NS, &unbonding_key(addr_arg("who")?)).unwrap_or(json!([]))),
+            "total_bonded" => Ok(json!(get::<u64>(ctx, NS, "total_bonded").unwrap_or(0))),
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_mut, dispatch_query, token};
+    use std::sync::Arc;
+
+    /// A ctx with a bonded-to-be validator and a delegator holding 100 each.
+    fn setup() -> (Ctx, String, String, String) {
+        let (_, pk) = pq::keygen_mldsa3();
+        let (validator, delegator) = (pq::address_from_pk(&pk), addr('d'));
+        let mut ctx = testutil::ctx(vec![Arc::new(Staking)]);
+        token::mint_to(&mut ctx, &validator, 100).unwrap();
+        token::mint_to(&mut ctx, &delegator, 100).unwrap();
+        ctx.set_height(10);
+        (ctx, validator, hex::encode(pk), delegator)
+    }
+
+    fn balance(ctx: &Ctx, who: &str) -> Value {
+        dispatch_query(ctx, "token", "balance_of", &json!({"who": who})).unwrap()
+    }
+
+    #[test]
+    fn bond_delegate_unbond_and_withdraw_after_the_period() {
+        let (mut ctx, v, pk, d) = setup();
+        dispatch_mut(&mut ctx, &v, NS, "bond", &json!({"amount": 50, "pk": pk, "url": "http://v"})).unwrap();
+        dispatch_mut(&mut ctx, &d, NS, "delegate", &json!({"validator": v, "amount": 30})).unwrap();
+        assert_eq!(validator(&ctx, &v).unwrap().stake(), 80);
+        assert_eq!(balance(&ctx, &module_addr(NS)), json!(80));
+
+        let out = dispatch_mut(&mut ctx, &d, NS, "unbond", &json!({"validator": v, "amount": 30})).unwrap();
+        assert_eq!(out["release_height"], json!(10 + unbonding_blocks()));
+        dispatch_mut(&mut ctx, &v, NS, "unbond", &json!({"amount": 20})).unwrap();
+        let stake = validator(&ctx, &v).unwrap();
+        assert_eq!((stake.self_bond, stake.delegated), (30, 0));
+        assert_eq!(dispatch_query(&ctx, NS, "total_bonded", &json!({})).unwrap(), json!(30));
+
+        ctx.set_height(9 + unbonding_blocks());
+        let err = dispatch_mut(&mut ctx, &d, NS, "withdraw", &json!({})).unwrap_err();
+        assert_eq!(err.to_string(), "nothing to withdraw");
+        ctx.set_height(10 + unbonding_blocks());
+        dispatch_mut(&mut ctx, &d, NS, "withdraw", &json!({})).unwrap();
+        dispatch_mut(&mut ctx, &v, NS, "withdraw", &json!({})).unwrap();
+        assert_eq!(balance(&ctx, &d), json!(100));
+        assert_eq!(balance(&ctx, &v), json!(70));
+        assert_eq!(balance(&ctx, &module_addr(NS)), json!(30));
+    }
+
+    #[test]
+    fn self_delegation_is_rejected_and_unbond_checks_what_was_bonded() {
+        let (mut ctx, v, pk, d) = setup();
+        dispatch_mut(&mut ctx, &v, NS, "bond", &json!({"amount": 50, "pk": pk, "url": "http://v"})).unwrap();
+        let err = dispatch_mut(&mut ctx, &v, NS, "delegate", &json!({"validator": v, "amount": 10})).unwrap_err();
+        assert!(matches!(err, CtxError::Logic(_)), "{}", err);
+
+        let err = dispatch_mut(&mut ctx, &d, NS, "unbond", &json!({"validator": v, "amount": 1})).unwrap_err();
+        assert_eq!(err.to_string(), "insufficient stake");
+        let err = dispatch_mut(&mut ctx, &v, NS, "unbond", &json!({"amount": 51})).unwrap_err();
+        assert_eq!(err.to_string(), "insufficient stake");
+        assert_eq!(validator(&ctx, &v).unwrap().stake(), 50);
+    }
+
+    #[test]
+    fn only_validators_with_the_minimum_stake_are_active() {
+        let (mut ctx, v, pk, d) = setup();
+        let err = dispatch_mut(&mut ctx, &v, NS, "bond", &json!({"amount": 50, "pk": pk, "url": "v:1"})).unwrap_err();
+        assert!(matches!(err, CtxError::BadArgs(_)), "{}", err);
+        dispatch_mut(&mut ctx, &v, NS, "bond", &json!({"amount": 100, "pk": pk, "url": "http://v"})).unwrap();
+        assert!(active_set(&ctx).is_empty());
+
+        token::mint_to(&mut ctx, &d, min_stake()).unwrap();
+        dispatch_mut(&mut ctx, &d, NS, "delegate", &json!({"validator": v, "amount": min_stake() - 100})).unwrap();
+        let active = active_set(&ctx);
+        assert_eq!(active.len(), 1);
+        assert_eq!((active[0].0.url.as_str(), active[0].1), ("http://v", min_stake()));
+    }
+}
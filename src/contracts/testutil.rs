@@ -0,0 +1,15 @@
+//! Fixtures shared by the contract and runtime tests.
+use super::{token, Contract, Ctx};
+use std::sync::Arc;
+
+/// A well-formed address made of one repeated hex digit.
+pub fn addr(c: char) -> String { c.to_string().repeat(128) }
+
+/// A ctx with `token`, owned and minted by `addr('a')`, plus `contracts`.
+pub fn ctx(contracts: Vec<Arc<dyn Contract>>) -> Ctx {
+    let mut ctx = Ctx::default();
+    ctx.register(Arc::new(token::Token));
+    for c in contracts { ctx.register(c); }
+    token::init(&mut ctx, &addr('a'), &addr('a'));
+    ctx
+}
@@ -1,55 +1,163 @@
-use super::{Ctx, Contract, CtxResult, CtxError};
+use super::{add_u64, addr_arg, amount_arg, sub_u64, Ctx, Contract, CtxResult, CtxError};
 use serde_json::{json, Value};
 
 pub struct Token;
 
-fn add_u64(a: u64, b: u64) -> Result<u64, CtxError> {
-    a.checked_add(b).ok_or_else(|| CtxError::Logic("overflow".into()))
+fn balance(ctx: &Ctx, addr: &str) -> u64 {
+    ctx.ns("token").and_then(|m| m.get(addr)).and_then(|v| v.as_u64()).unwrap_or(0)
 }
-fn sub_u64(a: u64, b: u64) -> Result<u64, CtxError> {
-    a.checked_sub(b).ok_or_else(|| CtxError::Logic("underflow".into()))
+
+fn role(ctx: &Ctx, key: &str) -> Option<String> {
+    ctx.ns("token").and_then(|m| m.get(key)).and_then(|v| v.as_str()).map(String::from)
+}
+
+fn require_role(ctx: &Ctx, key: &str, caller: &str) -> Result<(), CtxError> {
+    if role(ctx, key).as_deref() == Some(caller) { Ok(()) }
+    else { Err(CtxError::Logic(format!("caller is not the {}", key))) }
+}
+
+/// Genesis state: the `owner` and `minter` roles, and the metadata from
+/// `QSC_TOKEN_NAME`, `QSC_TOKEN_SYMBOL` and `QSC_TOKEN_DECIMALS`.
+pub fn init(ctx: &mut Ctx, owner: &str, minter: &str) {
+    let name = std::env::var("QSC_TOKEN_NAME").unwrap_or_else(|_| "QSC Token".into());
+    let symbol = std::env::var("QSC_TOKEN_SYMBOL").unwrap_or_else(|_| "QSC".into());
+    let decimals: u8 = std::env::var("QSC_TOKEN_DECIMALS").ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let mut ns = ctx.ns_mut("token");
+    ns.insert("owner".into(), json!(owner));
+    ns.insert("minter".into(), json!(minter));
+    ns.insert("name".into(), json!(name));
+    ns.insert("symbol".into(), json!(symbol));
+    ns.insert("decimals".into(), json!(decimals));
+}
+
+/// Allowance keys contain `:`, so they never collide with 128-hex balance keys
+/// or `total_supply`.
+fn allowance_key(owner: &str, spender: &str) -> String {
+    format!("allowance:{}:{}", owner, spender)
+}
+
+fn allowance(ctx: &Ctx, owner: &str, spender: &str) -> u64 {
+    ctx.ns("token").and_then(|m| m.get(&allowance_key(owner, spender))).and_then(|v| v.as_u64()).unwrap_or(0)
 }
-fn is_valid_addr(s: &str) -> bool {
-    s.len() == 128 && s.chars().all(|c| c.is_ascii_hexdigit())
+
+fn set_allowance(ctx: &mut Ctx, owner: &str, spender: &str, amount: u64) -> Value {
+    let key = allowance_key(owner, spender);
+    let mut ns = ctx.ns_mut("token");
+    if amount == 0 { ns.remove(&key); } else { ns.insert(key, json!(amount)); }
+    json!({"ok": true, "event": "Approval", "owner": owner, "spender": spender, "amount": amount})
+}
+
+/// Moves `amount` from `from` to `to`; both balances are checked before
+/// either is written.
+fn move_balance(ctx: &mut Ctx, from: &str, to: &str, amount: u64) -> Result<(), CtxError> {
+    if to == from { return Err(CtxError::Logic("self-transfer not allowed".into())); }
+    let new_from = sub_u64(balance(ctx, from), amount)?;
+    let new_to = add_u64(balance(ctx, to), amount)?;
+    let mut ns = ctx.ns_mut("token");
+    ns.insert(from.into(), json!(new_from));
+    ns.insert(to.into(), json!(new_to));
+    Ok(())
+}
+
+/// Takes `amount` from `addr` outside of any call, e.g. a tx fee.
+pub fn debit(ctx: &mut Ctx, addr: &str, amount: u64) -> Result<(), CtxError> {
+    if amount == 0 { return Ok(()); }
+    let bal = sub_u64(balance(ctx, addr), amount)?;
+    ctx.ns_mut("token").insert(addr.into(), json!(bal));
+    Ok(())
+}
+
+/// Adds `amount` of existing supply to `addr`, e.g. collected fees.
+pub fn credit(ctx: &mut Ctx, addr: &str, amount: u64) -> Result<(), CtxError> {
+    if amount == 0 { return Ok(()); }
+    let bal = add_u64(balance(ctx, addr), amount)?;
+    ctx.ns_mut("token").insert(addr.into(), json!(bal));
+    Ok(())
+}
+
+/// Creates `amount` new tokens for `to`, within `QSC_TOKEN_MAX_SUPPLY`.
+pub fn mint_to(ctx: &mut Ctx, to: &str, amount: u64) -> Result<(), CtxError> {
+    if amount == 0 { return Ok(()); }
+    let cap: u64 = std::env::var("QSC_TOKEN_MAX_SUPPLY").ok().and_then(|s| s.parse().ok()).unwrap_or(u64::MAX);
+    let total = ctx.ns("token").and_then(|m| m.get("total_supply")).and_then(|v| v.as_u64()).unwrap_or(0);
+    let new_total = add_u64(total, amount)?;
+    if new_total > cap { return Err(CtxError::Logic("max supply exceeded".into())); }
+    credit(ctx, to, amount)?;
+    ctx.ns_mut("token").insert("total_supply".into(), json!(new_total));
+    Ok(())
 }
 
 impl Contract for Token {
     fn name(&self) -> &'static str { "token" }
 
     fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
-        let ns = ctx.ns_mut(self.name());
         match method {
             "mint" => {
-                let to = args.get("to").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("to".into()))?;
-                if !is_valid_addr(to) { return Err(CtxError::BadArgs("to (invalid addr)".into())); }
-                let amount = args.get("amount").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("amount".into()))?;
-                if amount == 0 { return Err(CtxError::BadArgs("amount must be > 0".into())); }
-
-                let cap: u64 = std::env::var("QSC_TOKEN_MAX_SUPPLY").ok().and_then(|s| s.parse().ok()).unwrap_or(u64::MAX);
-                let total = ns.get("total_supply").and_then(|v| v.as_u64()).unwrap_or(0);
-                let new_total = add_u64(total, amount)?;
-                if new_total > cap { return Err(CtxError::Logic("max supply exceeded".into())); }
-                ns.insert("total_supply".into(), json!(new_total));
+                require_role(ctx, "minter", caller)?;
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
 
-                let bal = ns.get(to).and_then(|v| v.as_u64()).unwrap_or(0);
-                ns.insert(to.into(), json!(add_u64(bal, amount)?));
+                mint_to(ctx, to, amount)?;
                 Ok(json!({"ok": true, "event":"Mint","to":to,"amount":amount}))
             }
+            // Destroys the caller's own tokens.
+            "burn" => {
+                let amount = amount_arg(args)?;
+                let bal = sub_u64(balance(ctx, caller), amount)?;
+                let total = sub_u64(ctx.ns("token").and_then(|m| m.get("total_supply")).and_then(|v| v.as_u64()).unwrap_or(0), amount)?;
+                let mut ns = ctx.ns_mut(self.name());
+                ns.insert(caller.into(), json!(bal));
+                ns.insert("total_supply".into(), json!(total));
+                Ok(json!({"ok": true, "event":"Burn","from":caller,"amount":amount}))
+            }
+            "set_minter" => {
+                require_role(ctx, "owner", caller)?;
+                let minter = addr_arg(args, "minter")?;
+                ctx.ns_mut(self.name()).insert("minter".into(), json!(minter));
+                Ok(json!({"ok": true, "event":"MinterChanged","minter":minter}))
+            }
+            "transfer_ownership" => {
+                require_role(ctx, "owner", caller)?;
+                let owner = addr_arg(args, "owner")?;
+                ctx.ns_mut(self.name()).insert("owner".into(), json!(owner));
+                Ok(json!({"ok": true, "event":"OwnershipTransferred","from":caller,"to":owner}))
+            }
             "transfer" => {
-                let to = args.get("to").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("to".into()))?;
-                if !is_valid_addr(to) { return Err(CtxError::BadArgs("to (invalid addr)".into())); }
-                if to == caller { return Err(CtxError::Logic("self-transfer not allowed".into())); }
-                let amount = args.get("amount").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("amount".into()))?;
-                if amount == 0 { return Err(CtxError::BadArgs("amount must be > 0".into())); }
-
-                let from_bal = ns.get(caller).and_then(|v| v.as_u64()).unwrap_or(0);
-                let new_from = sub_u64(from_bal, amount)?;
-                ns.insert(caller.into(), json!(new_from));
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
 
-                let to_bal = ns.get(to).and_then(|v| v.as_u64()).unwrap_or(0);
-                ns.insert(to.into(), json!(add_u64(to_bal, amount)?));
+                move_balance(ctx, caller, to, amount)?;
                 Ok(json!({"ok": true, "event":"Transfer","from":caller,"to":to,"amount":amount}))
             }
+            // Sets the caller's allowance for `spender`, replacing the old one; 0 revokes it.
+            "approve" => {
+                let spender = addr_arg(args, "spender")?;
+                let amount = args.get("amount").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("amount".into()))?;
+                Ok(set_allowance(ctx, caller, spender, amount))
+            }
+            "increase_allowance" => {
+                let spender = addr_arg(args, "spender")?;
+                let amount = add_u64(allowance(ctx, caller, spender), amount_arg(args)?)?;
+                Ok(set_allowance(ctx, caller, spender, amount))
+            }
+            "decrease_allowance" => {
+                let spender = addr_arg(args, "spender")?;
+                let amount = allowance(ctx, caller, spender).checked_sub(amount_arg(args)?)
+                    .ok_or_else(|| CtxError::Logic("allowance below zero".into()))?;
+                Ok(set_allowance(ctx, caller, spender, amount))
+            }
+            // The caller spends `from`'s tokens within its allowance.
+            "transfer_from" => {
+                let from = addr_arg(args, "from")?;
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
+                let left = allowance(ctx, from, caller).checked_sub(amount)
+                    .ok_or_else(|| CtxError::Logic("insufficient allowance".into()))?;
+
+                move_balance(ctx, from, to, amount)?;
+                set_allowance(ctx, from, caller, left);
+                Ok(json!({"ok": true, "event":"Transfer","from":from,"to":to,"amount":amount,"spender":caller,"allowance":left}))
+            }
             _ => Err(CtxError::MethodNotFound),
         }
     }
@@ -66,7 +174,66 @@ impl Contract for Token {
                 let v = ns.and_then(|m| m.get(who)).cloned().unwrap_or(json!(0));
                 Ok(v)
             }
+            "name" | "symbol" | "decimals" | "owner" | "minter" => {
+                Ok(ns.and_then(|m| m.get(method)).cloned().unwrap_or(Value::Null))
+            }
+            "metadata" => {
+                let get = |key: &str| ns.and_then(|m| m.get(key)).cloned().unwrap_or(Value::Null);
+                Ok(json!({"name": get("name"), "symbol": get("symbol"), "decimals": get("decimals")}))
+            }
+            "allowance" => {
+                let owner = args.get("owner").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("owner".into()))?;
+                let spender = args.get("spender").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("spender".into()))?;
+                Ok(json!(allowance(ctx, owner, spender)))
+            }
             _ => Err(CtxError::MethodNotFound),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_mut, dispatch_query};
+
+    fn query(ctx: &Ctx, method: &str, args: Value) -> Value {
+        dispatch_query(ctx, "token", method, &args).unwrap()
+    }
+
+    #[test]
+    fn transfer_from_spends_allowance() {
+        let (a, b, c) = (addr('a'), addr('b'), addr('c'));
+        let mut ctx = testutil::ctx(vec![]);
+        mint_to(&mut ctx, &a, 100).unwrap();
+
+        let out = dispatch_mut(&mut ctx, &a, "token", "approve", &json!({"spender": b, "amount": 30})).unwrap();
+        assert_eq!(out["event"], "Approval");
+        let err = dispatch_mut(&mut ctx, &b, "token", "transfer_from", &json!({"from": a, "to": c, "amount": 40})).unwrap_err();
+        assert_eq!(err.to_string(), "insufficient allowance");
+        dispatch_mut(&mut ctx, &b, "token", "transfer_from", &json!({"from": a, "to": c, "amount": 25})).unwrap();
+
+        assert_eq!(query(&ctx, "allowance", json!({"owner": a, "spender": b})), json!(5));
+        assert_eq!(query(&ctx, "balance_of", json!({"who": c})), json!(25));
+        assert_eq!(query(&ctx, "balance_of", json!({"who": a})), json!(75));
+    }
+
+    #[test]
+    fn only_minter_mints_and_owner_moves_roles() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = testutil::ctx(vec![]);
+
+        let err = dispatch_mut(&mut ctx, &b, "token", "mint", &json!({"to": b, "amount": 5})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not the minter");
+        let err = dispatch_mut(&mut ctx, &b, "token", "set_minter", &json!({"minter": b})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not the owner");
+        dispatch_mut(&mut ctx, &a, "token", "set_minter", &json!({"minter": b})).unwrap();
+        dispatch_mut(&mut ctx, &b, "token", "mint", &json!({"to": b, "amount": 5})).unwrap();
+        dispatch_mut(&mut ctx, &b, "token", "burn", &json!({"amount": 2})).unwrap();
+
+        assert_eq!(query(&ctx, "minter", json!({})), json!(b));
+        assert_eq!(query(&ctx, "total_supply", json!({})), json!(3));
+        dispatch_mut(&mut ctx, &a, "token", "transfer_ownership", &json!({"owner": b})).unwrap();
+        assert_eq!(query(&ctx, "owner", json!({})), json!(b));
+    }
+}
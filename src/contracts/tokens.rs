@@ -0,0 +1,269 @@
+use super::{add_u64, addr_arg, amount_arg, get, is_valid_id, put, str_arg, sub_u64, Ctx, Contract, CtxResult, CtxError};
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+/// Factory for user-created fungible tokens. Each token has its own id,
+/// metadata, supply cap and roles; its balances and allowances live in this
+/// namespace under keys prefixed with the id.
+pub struct Tokens;
+
+#[derive(Serialize, Deserialize)]
+struct TokenInfo {
+    id: String,
+    name: String,
+    symbol: String,
+    decimals: u8,
+    /// `None` means no cap
+    max_supply: Option<u64>,
+    total_supply: u64,
+    owner: String,
+    minter: String,
+}
+
+const NS: &str = "tokens";
+const MAX_ID_LEN: usize = 32;
+
+fn token_key(id: &str) -> String { format!("token:{}", id) }
+fn balance_key(id: &str, addr: &str) -> String { format!("balance:{}:{}", id, addr) }
+fn allowance_key(id: &str, owner: &str, spender: &str) -> String { format!("allowance:{}:{}:{}", id, owner, spender) }
+
+/// An optional u64 arg: missing or `null` is `None`, anything else must be a u64.
+fn opt_u64_arg(args: &Value, name: &str) -> Result<Option<u64>, CtxError> {
+    match args.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v.as_u64().map(Some).ok_or_else(|| CtxError::BadArgs(format!("{} (must be a u64)", name))),
+    }
+}
+/// A required string arg of `min..=max` bytes.
+fn bounded_str_arg<'a>(args: &'a Value, name: &str, min: usize, max: usize) -> Result<&'a str, CtxError> {
+    let s = str_arg(args, name)?;
+    if s.len() < min || s.len() > max { return Err(CtxError::BadArgs(format!("{} ({}-{} chars)", name, min, max))); }
+    Ok(s)
+}
+
+fn token(ctx: &Ctx, args: &Value) -> Result<TokenInfo, CtxError> {
+    let id = str_arg(args, "id")?;
+    get(ctx, NS, &token_key(id)).ok_or_else(|| CtxError::Logic("unknown token".into()))
+}
+
+fn balance(ctx: &Ctx, id: &str, addr: &str) -> u64 {
+    get(ctx, NS, &balance_key(id, addr)).unwrap_or(0)
+}
+
+fn set_balance(ctx: &mut Ctx, id: &str, addr: &str, amount: u64) {
+    let key = balance_key(id, addr);
+    if amount == 0 { ctx.ns_mut(NS).remove(&key); } else { put(ctx, NS, key, &amount); }
+}
+
+fn mint(ctx: &mut Ctx, t: &mut TokenInfo, to: &str, amount: u64) -> Result<(), CtxError> {
+    let total = add_u64(t.total_supply, amount)?;
+    if t.max_supply.is_some_and(|cap| total > cap) { return Err(CtxError::Logic("max supply exceeded".into())); }
+    let bal = add_u64(balance(ctx, &t.id, to), amount)?;
+    t.total_supply = total;
+    set_balance(ctx, &t.id, to, bal);
+    put(ctx, NS, token_key(&t.id), t);
+    Ok(())
+}
+
+/// Moves `amount` of token `id`; both balances are checked before either is written.
+fn move_balance(ctx: &mut Ctx, id: &str, from: &str, to: &str, amount: u64) -> Result<(), CtxError> {
+    if to == from { return Err(CtxError::Logic("self-transfer not allowed".into())); }
+    let new_from = sub_u64(balance(ctx, id, from), amount)?;
+    let new_to = add_u64(balance(ctx, id, to), amount)?;
+    set_balance(ctx, id, from, new_from);
+    set_balance(ctx, id, to, new_to);
+    Ok(())
+}
+
+impl Contract for Tokens {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            // The creator becomes owner and minter and receives `initial_supply`.
+            "create" => {
+                let id = str_arg(args, "id")?;
+                if !is_valid_id(id, MAX_ID_LEN) { return Err(CtxError::BadArgs(format!("id (1-{} chars of [A-Za-z0-9_-])", MAX_ID_LEN))); }
+                if ctx.ns(NS).is_some_and(|m| m.contains_key(&token_key(id))) {
+                    return Err(CtxError::Logic("token id already exists".into()));
+                }
+                let decimals = opt_u64_arg(args, "decimals")?.unwrap_or(0);
+                if decimals > 18 { return Err(CtxError::BadArgs("decimals must be <= 18".into())); }
+                let mut t = TokenInfo {
+                    id: id.into(),
+                    name: bounded_str_arg(args, "name", 1, 64)?.into(),
+                    symbol: bounded_str_arg(args, "symbol", 1, 16)?.into(),
+                    decimals: decimals as u8,
+                    max_supply: opt_u64_arg(args, "max_supply")?,
+                    total_supply: 0,
+                    owner: caller.into(),
+                    minter: caller.into(),
+                };
+                let initial = opt_u64_arg(args, "initial_supply")?.unwrap_or(0);
+                put(ctx, NS, token_key(id), &t);
+                if initial > 0 { mint(ctx, &mut t, caller, initial)?; }
+                Ok(json!({"ok": true, "event": "TokenCreated", "id": id, "owner": caller, "initial_supply": initial}))
+            }
+            "mint" => {
+                let mut t = token(ctx, args)?;
+                if t.minter != caller { return Err(CtxError::Logic("caller is not the minter".into())); }
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
+                mint(ctx, &mut t, to, amount)?;
+                Ok(json!({"ok": true, "event": "Mint", "id": t.id, "to": to, "amount": amount}))
+            }
+            "burn" => {
+                let mut t = token(ctx, args)?;
+                let amount = amount_arg(args)?;
+                let bal = sub_u64(balance(ctx, &t.id, caller), amount)?;
+                t.total_supply = sub_u64(t.total_supply, amount)?;
+                set_balance(ctx, &t.id, caller, bal);
+                put(ctx, NS, token_key(&t.id), &t);
+                Ok(json!({"ok": true, "event": "Burn", "id": t.id, "from": caller, "amount": amount}))
+            }
+            "transfer" => {
+                let t = token(ctx, args)?;
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
+                move_balance(ctx, &t.id, caller, to, amount)?;
+                Ok(json!({"ok": true, "event": "Transfer", "id": t.id, "from": caller, "to": to, "amount": amount}))
+            }
+            // Sets the caller's allowance for `spender`, replacing the old one; 0 revokes it.
+            "approve" => {
+                let t = token(ctx, args)?;
+                let spender = addr_arg(args, "spender")?;
+                let amount = args.get("amount").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("amount".into()))?;
+                let key = allowance_key(&t.id, caller, spender);
+                if amount == 0 { ctx.ns_mut(NS).remove(&key); } else { put(ctx, NS, key, &amount); }
+                Ok(json!({"ok": true, "event": "Approval", "id": t.id, "owner": caller, "spender": spender, "amount": amount}))
+            }
+            "transfer_from" => {
+                let t = token(ctx, args)?;
+                let from = addr_arg(args, "from")?;
+                let to = addr_arg(args, "to")?;
+                let amount = amount_arg(args)?;
+                let key = allowance_key(&t.id, from, caller);
+                let left = get::<u64>(ctx, NS, &key).unwrap_or(0).checked_sub(amount)
+                    .ok_or_else(|| CtxError::Logic("insufficient allowance".into()))?;
+                move_balance(ctx, &t.id, from, to, amount)?;
+                if left == 0 { ctx.ns_mut(NS).remove(&key); } else { put(ctx, NS, key, &left); }
+                Ok(json!({"ok": true, "event": "Transfer", "id": t.id, "from": from, "to": to, "amount": amount, "spender": caller, "allowance": left}))
+            }
+            "set_minter" => {
+                let mut t = token(ctx, args)?;
+                if t.owner != caller { return Err(CtxError::Logic("caller is not the owner".into())); }
+                t.minter = addr_arg(args, "minter")?.into();
+                put(ctx, NS, token_key(&t.id), &t);
+                Ok(json!({"ok": true, "event": "MinterChanged", "id": t.id, "minter": t.minter}))
+            }
+            "transfer_ownership" => {
+                let mut t = token(ctx, args)?;
+                if t.owner != caller { return Err(CtxError::Logic("caller is not the owner".into())); }
+                t.owner = addr_arg(args, "owner")?.into();
+                put(ctx, NS, token_key(&t.id), &t);
+                Ok(json!({"ok": true, "event": "OwnershipTransferred", "id": t.id, "from": caller, "to": t.owner}))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "token" => Ok(json!(token(ctx, args)?)),
+            "tokens" => {
+                let mut ids: Vec<&str> = ctx.ns(NS).into_iter().flatten()
+                    .filter_map(|(k, _)| k.strip_prefix("token:"))
+                    .collect();
+                ids.sort();
+                Ok(json!(ids))
+            }
+            "total_supply" => Ok(json!(token(ctx, args)?.total_supply)),
+            "balance_of" => {
+                let t = token(ctx, args)?;
+                Ok(json!(balance(ctx, &t.id, str_arg(args, "who")?)))
+            }
+            "allowance" => {
+                let t = token(ctx, args)?;
+                let key = allowance_key(&t.id, str_arg(args, "owner")?, str_arg(args, "spender")?);
+                Ok(json!(get::<u64>(ctx, NS, &key).unwrap_or(0)))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::{dispatch_mut, dispatch_query};
+    use std::sync::Arc;
+
+    fn create(ctx: &mut Ctx, caller: &str, args: Value) -> CtxResult {
+        let mut all = json!({"id": "gold", "name": "Gold", "symbol": "GLD"});
+        all.as_object_mut().unwrap().extend(args.as_object().unwrap().clone());
+        dispatch_mut(ctx, caller, NS, "create", &all)
+    }
+
+    fn balance_of(ctx: &Ctx, id: &str, who: &str) -> Value {
+        dispatch_query(ctx, NS, "balance_of", &json!({"id": id, "who": who})).unwrap()
+    }
+
+    #[test]
+    fn create_rejects_bad_args_and_reverts_over_cap() {
+        let a = addr('a');
+        let mut ctx = testutil::ctx(vec![Arc::new(Tokens)]);
+        for bad in [json!({"max_supply": "10"}), json!({"decimals": -1}), json!({"initial_supply": 1.5}), json!({"symbol": ""}), json!({"name": "n".repeat(65)})] {
+            assert!(matches!(create(&mut ctx, &a, bad.clone()), Err(CtxError::BadArgs(_))), "{}", bad);
+        }
+
+        let err = create(&mut ctx, &a, json!({"max_supply": 10, "initial_supply": 11})).unwrap_err();
+        assert_eq!(err.to_string(), "max supply exceeded");
+        assert!(dispatch_query(&ctx, NS, "token", &json!({"id": "gold"})).is_err());
+        create(&mut ctx, &a, json!({"max_supply": 10, "initial_supply": 10, "decimals": null})).unwrap();
+        assert_eq!(balance_of(&ctx, "gold", &a), json!(10));
+    }
+
+    #[test]
+    fn only_minter_mints() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = testutil::ctx(vec![Arc::new(Tokens)]);
+        create(&mut ctx, &a, json!({})).unwrap();
+        let err = dispatch_mut(&mut ctx, &b, NS, "mint", &json!({"id": "gold", "to": b, "amount": 5})).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not the minter");
+        dispatch_mut(&mut ctx, &a, NS, "mint", &json!({"id": "gold", "to": b, "amount": 5})).unwrap();
+        assert_eq!(balance_of(&ctx, "gold", &b), json!(5));
+        assert_eq!(dispatch_query(&ctx, NS, "total_supply", &json!({"id": "gold"})).unwrap(), json!(5));
+    }
+
+    #[test]
+    fn transfer_from_spends_allowance() {
+        let (a, b, c) = (addr('a'), addr('b'), addr('c'));
+        let mut ctx = testutil::ctx(vec![Arc::new(Tokens)]);
+        create(&mut ctx, &a, json!({"initial_supply": 100})).unwrap();
+        dispatch_mut(&mut ctx, &a, NS, "approve", &json!({"id": "gold", "spender": b, "amount": 30})).unwrap();
+
+        let err = dispatch_mut(&mut ctx, &b, NS, "transfer_from", &json!({"id": "gold", "from": a, "to": c, "amount": 31})).unwrap_err();
+        assert_eq!(err.to_string(), "insufficient allowance");
+        let out = dispatch_mut(&mut ctx, &b, NS, "transfer_from", &json!({"id": "gold", "from": a, "to": c, "amount": 30})).unwrap();
+        assert_eq!(out["allowance"], json!(0));
+        assert_eq!(dispatch_query(&ctx, NS, "allowance", &json!({"id": "gold", "owner": a, "spender": b})).unwrap(), json!(0));
+        assert_eq!(balance_of(&ctx, "gold", &c), json!(30));
+        assert_eq!(balance_of(&ctx, "gold", &a), json!(70));
+    }
+
+    #[test]
+    fn balances_of_different_tokens_are_isolated() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = testutil::ctx(vec![Arc::new(Tokens)]);
+        create(&mut ctx, &a, json!({"initial_supply": 100})).unwrap();
+        create(&mut ctx, &a, json!({"id": "silver", "symbol": "SLV", "initial_supply": 7})).unwrap();
+
+        dispatch_mut(&mut ctx, &a, NS, "transfer", &json!({"id": "silver", "to": b, "amount": 7})).unwrap();
+        let err = dispatch_mut(&mut ctx, &a, NS, "transfer", &json!({"id": "silver", "to": b, "amount": 1})).unwrap_err();
+        assert_eq!(err.to_string(), "underflow");
+        assert_eq!(balance_of(&ctx, "gold", &a), json!(100));
+        assert_eq!(balance_of(&ctx, "gold", &b), json!(0));
+        assert_eq!(balance_of(&ctx, "silver", &b), json!(7));
+    }
+}
@@ -0,0 +1,308 @@
+use super::{Ctx, Contract, CtxResult, CtxError};
+use crate::consensus::{self, Validator};
+use crate::pq;
+use serde::{Serialize, Deserialize};
+use serde_json::{json, Value};
+
+/// Governance over the PoA validator set. Current validators propose and
+/// approve additions and removals; once a quorum approves, the new set is
+/// scheduled as an epoch and leads blocks from its `from_height` onward.
+/// Until the first change, the set from `QSC_VALIDATORS_JSON` applies.
+pub struct Validators;
+
+/// A validator set and the first block height it applies to.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Epoch {
+    pub from_height: u64,
+    pub validators: Vec<Validator>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Proposal {
+    id: u64,
+    /// `add` or `remove`
+    action: String,
+    validator: Validator,
+    epoch_height: u64,
+    proposer: String,
+    approvals: Vec<String>,
+    executed: bool,
+}
+
+const NS: &str = "validators";
+
+fn env_set() -> Vec<Validator> {
+    consensus::PoAConfig::from_env().map(|c| c.validators).unwrap_or_default()
+}
+
+fn epochs(ctx: &Ctx) -> Vec<Epoch> {
+    ctx.ns(NS).and_then(|m| m.get("epochs"))
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+        .unwrap_or_default()
+}
+
+/// The governed validator set for blocks at `height`, or `None` while the
+/// genesis set from the environment still applies.
+pub fn set_at(ctx: &Ctx, height: u64) -> Option<Vec<Validator>> {
+    epochs(ctx).into_iter().rev().find(|e| e.from_height <= height).map(|e| e.validators)
+}
+
+/// Validators allowed to vote now.
+fn active_set(ctx: &Ctx) -> Vec<Validator> {
+    set_at(ctx, ctx.height()).unwrap_or_else(env_set)
+}
+
+/// The set once every scheduled change is in effect; proposals apply to it.
+fn latest_set(ctx: &Ctx) -> Vec<Validator> {
+    epochs(ctx).pop().map(|e| e.validators).unwrap_or_else(env_set)
+}
+
+fn addr_of(v: &Validator) -> String {
+    hex::decode(&v.pk).map(|pk| pq::address_from_pk(&pk)).unwrap_or_default()
+}
+
+fn position(set: &[Validator], pk: &str) -> Option<usize> {
+    set.iter().position(|v| v.pk.eq_ignore_ascii_case(pk))
+}
+
+fn load_proposal(ctx: &Ctx, id: u64) -> Option<Proposal> {
+    ctx.ns(NS).and_then(|m| m.get(&format!("proposal:{}", id)))
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+fn save_proposal(ctx: &mut Ctx, p: &Proposal) {
+    ctx.ns_mut(NS).insert(format!("proposal:{}", p.id), serde_json::to_value(p).unwrap());
+}
+
+fn require_validator(ctx: &Ctx, caller: &str) -> Result<(), CtxError> {
+    if active_set(ctx).iter().any(|v| addr_of(v) == caller) { Ok(()) }
+    else { Err(CtxError::Logic("caller is not a validator".into())) }
+}
+
+/// The set that results from applying `p` to `set`.
+fn apply(mut set: Vec<Validator>, p: &Proposal) -> Result<Vec<Validator>, CtxError> {
+    match (p.action.as_str(), position(&set, &p.validator.pk)) {
+        ("add", None) => set.push(p.validator.clone()),
+        ("add", Some(_)) => return Err(CtxError::Logic("already a validator".into())),
+        ("remove", Some(_)) if set.len() == 1 => return Err(CtxError::Logic("cannot remove the last validator".into())),
+        ("remove", Some(i)) => { set.remove(i); }
+        ("remove", None) => return Err(CtxError::Logic("not a validator".into())),
+        _ => return Err(CtxError::BadArgs("action must be add or remove".into())),
+    }
+    Ok(set)
+}
+
+/// Executes `p` once approvals from the active set reach a quorum.
+fn tally(ctx: &mut Ctx, mut p: Proposal) -> CtxResult {
+    let active: Vec<String> = active_set(ctx).iter().map(addr_of).collect();
+    let votes = p.approvals.iter().filter(|a| active.contains(a)).count();
+    if !consensus::has_quorum(votes as u64, active.len() as u64) {
+        let out = json!({"ok": true, "event": "ValidatorApproved", "id": p.id, "approvals": votes, "needed": active.len() * 2 / 3 + 1});
+        save_proposal(ctx, &p);
+        return Ok(out);
+    }
+    let set = apply(latest_set(ctx), &p)?;
+    let mut schedule = epochs(ctx);
+    // Heights up to the current block keep the leaders they were produced with.
+    let from_height = p.epoch_height
+        .max(ctx.height() + 1)
+        .max(schedule.last().map_or(0, |e| e.from_height));
+    match schedule.last_mut() {
+        Some(last) if last.from_height == from_height => last.validators = set,
+        _ => schedule.push(Epoch { from_height, validators: set }),
+    }
+    ctx.ns_mut(NS).insert("epochs".into(), serde_json::to_value(&schedule).unwrap());
+    p.executed = true;
+    save_proposal(ctx, &p);
+    Ok(json!({"ok": true, "event": "ValidatorSetChanged", "id": p.id, "action": p.action, "pk": p.validator.pk, "from_height": from_height}))
+}
+
+/// Removes a misbehaving validator from the set from the next block on,
+/// without a vote. Returns that height.
+pub fn jail(ctx: &mut Ctx, pk: &str) -> Result<u64, CtxError> {
+    let from_height = ctx.height() + 1;
+    let mut schedule = epochs(ctx);
+    // Start an epoch at the next block even when a change is already
+    // scheduled for later, so the removal does not wait for it.
+    if !schedule.iter().any(|e| e.from_height == from_height) {
+        let current = set_at(ctx, from_height).unwrap_or_else(env_set);
+        let at = schedule.iter().position(|e| e.from_height > from_height).unwrap_or(schedule.len());
+        schedule.insert(at, Epoch { from_height, validators: current });
+    }
+    let mut found = false;
+    for e in schedule.iter_mut().filter(|e| e.from_height >= from_height) {
+        let Some(i) = position(&e.validators, pk) else { continue };
+        if e.validators.len() == 1 { return Err(CtxError::Logic("cannot remove the last validator".into())); }
+        e.validators.remove(i);
+        found = true;
+    }
+    if !found { return Err(CtxError::Logic("not a validator".into())); }
+    ctx.ns_mut(NS).insert("epochs".into(), serde_json::to_value(&schedule).unwrap());
+    Ok(from_height)
+}
+
+impl Contract for Validators {
+    fn name(&self) -> &'static str { NS }
+
+    fn call(&self, ctx: &mut Ctx, caller: &str, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "propose" => {
+                require_validator(ctx, caller)?;
+                let action = args.get("action").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("action".into()))?;
+                let epoch_height = args.get("epoch_height").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("epoch_height".into()))?;
+                if epoch_height <= ctx.height() { return Err(CtxError::BadArgs("epoch_height must be in the future".into())); }
+                let validator: Validator = match action {
+                    "add" => serde_json::from_value(args.get("validator").cloned().unwrap_or(Value::Null))
+                        .map_err(|_| CtxError::BadArgs("validator {id,url,pk}".into()))?,
+                    "remove" => {
+                        let pk = args.get("pk").and_then(|v| v.as_str()).ok_or_else(|| CtxError::BadArgs("pk".into()))?;
+                        let set = latest_set(ctx);
+                        let i = position(&set, pk).ok_or_else(|| CtxError::Logic("not a validator".into()))?;
+                        set[i].clone()
+                    }
+                    _ => return Err(CtxError::BadArgs("action must be add or remove".into())),
+                };
+                if action == "add" && !hex::decode(&validator.pk).is_ok_and(|pk| pq::is_mldsa3_pk(&pk)) {
+                    return Err(CtxError::BadArgs("pk (not an ML-DSA-3 public key)".into()));
+                }
+
+                let id = ctx.ns(NS).and_then(|m| m.get("next_proposal")).and_then(|v| v.as_u64()).unwrap_or(0);
+                let p = Proposal {
+                    id, action: action.into(), validator, epoch_height,
+                    proposer: caller.into(), approvals: vec![caller.into()], executed: false,
+                };
+                // Reject proposals that could never execute before storing anything.
+                apply(latest_set(ctx), &p)?;
+                ctx.ns_mut(NS).insert("next_proposal".into(), json!(id + 1));
+                tally(ctx, p)
+            }
+            "approve" => {
+                require_validator(ctx, caller)?;
+                let id = args.get("id").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("id".into()))?;
+                let mut p = load_proposal(ctx, id).ok_or_else(|| CtxError::Logic("unknown proposal".into()))?;
+                if p.executed { return Err(CtxError::Logic("proposal already executed".into())); }
+                if p.approvals.iter().any(|a| a == caller) { return Err(CtxError::Logic("already approved".into())); }
+                p.approvals.push(caller.into());
+                tally(ctx, p)
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+
+    fn query(&self, ctx: &Ctx, method: &str, args: &Value) -> CtxResult {
+        match method {
+            "validators" => {
+                let height = args.get("height").and_then(|v| v.as_u64()).unwrap_or(ctx.height());
+                Ok(json!(set_at(ctx, height).unwrap_or_else(env_set)))
+            }
+            "epochs" => Ok(json!(epochs(ctx))),
+            "proposal" => {
+                let id = args.get("id").and_then(|v| v.as_u64()).ok_or_else(|| CtxError::BadArgs("id".into()))?;
+                load_proposal(ctx, id).map(|p| json!(p)).ok_or_else(|| CtxError::Logic("unknown proposal".into()))
+            }
+            _ => Err(CtxError::MethodNotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::contracts::dispatch_mut;
+    use std::sync::Arc;
+
+    fn validator(i: usize) -> Validator {
+        let (_, pk) = pq::keygen_mldsa3();
+        Validator { id: format!("v{}", i), url: format!("http://127.0.0.1:{}", 7000 + i), pk: hex::encode(pk) }
+    }
+
+    /// A ctx at height 10 whose governed set is `set` from genesis on.
+    fn setup(set: &[Validator]) -> Ctx {
+        let mut ctx = testutil::ctx(vec![Arc::new(Validators)]);
+        let schedule = vec![Epoch { from_height: 0, validators: set.to_vec() }];
+        ctx.ns_mut(NS).insert("epochs".into(), serde_json::to_value(schedule).unwrap());
+        ctx.set_height(10);
+        ctx
+    }
+
+    fn ids(set: Option<Vec<Validator>>) -> Vec<String> {
+        set.unwrap_or_default().into_iter().map(|v| v.id).collect()
+    }
+
+    #[test]
+    fn a_quorum_of_approvals_schedules_the_change_from_its_epoch() {
+        let set: Vec<Validator> = (0..3).map(validator).collect();
+        let mut ctx = setup(&set);
+        let add = json!({"action": "add", "validator": validator(3), "epoch_height": 20});
+        let err = dispatch_mut(&mut ctx, &addr('e'), NS, "propose", &add).unwrap_err();
+        assert_eq!(err.to_string(), "caller is not a validator");
+
+        let out = dispatch_mut(&mut ctx, &addr_of(&set[0]), NS, "propose", &add).unwrap();
+        assert_eq!((out["event"].clone(), out["needed"].clone()), (json!("ValidatorApproved"), json!(3)));
+        let err = dispatch_mut(&mut ctx, &addr_of(&set[0]), NS, "approve", &json!({"id": 0})).unwrap_err();
+        assert_eq!(err.to_string(), "already approved");
+        let out = dispatch_mut(&mut ctx, &addr_of(&set[1]), NS, "approve", &json!({"id": 0})).unwrap();
+        assert_eq!(out["approvals"], json!(2));
+        assert_eq!(ids(set_at(&ctx, 25)).len(), 3);
+
+        let out = dispatch_mut(&mut ctx, &addr_of(&set[2]), NS, "approve", &json!({"id": 0})).unwrap();
+        assert_eq!((out["event"].clone(), out["from_height"].clone()), (json!("ValidatorSetChanged"), json!(20)));
+        assert_eq!(ids(set_at(&ctx, 19)), ["v0", "v1", "v2"]);
+        assert_eq!(ids(set_at(&ctx, 20)), ["v0", "v1", "v2", "v3"]);
+        let err = dispatch_mut(&mut ctx, &addr_of(&set[2]), NS, "approve", &json!({"id": 0})).unwrap_err();
+        assert_eq!(err.to_string(), "proposal already executed");
+    }
+
+    #[test]
+    fn a_late_quorum_applies_from_the_next_block() {
+        let set: Vec<Validator> = (0..2).map(validator).collect();
+        let mut ctx = setup(&set);
+        dispatch_mut(&mut ctx, &addr_of(&set[0]), NS, "propose", &json!({"action": "remove", "pk": set[1].pk, "epoch_height": 12})).unwrap();
+        // Blocks up to the approving one keep the leaders they were made with.
+        ctx.set_height(15);
+        let out = dispatch_mut(&mut ctx, &addr_of(&set[1]), NS, "approve", &json!({"id": 0})).unwrap();
+        assert_eq!(out["from_height"], json!(16));
+        assert_eq!(ids(set_at(&ctx, 15)), ["v0", "v1"]);
+        assert_eq!(ids(set_at(&ctx, 16)), ["v0"]);
+    }
+
+    #[test]
+    fn added_keys_must_be_ml_dsa_keys_and_the_last_validator_stays() {
+        let set = vec![validator(0)];
+        let mut ctx = setup(&set);
+        let mut bad = validator(1);
+        bad.pk = "ab".repeat(32);
+        let err = dispatch_mut(&mut ctx, &addr_of(&set[0]), NS, "propose", &json!({"action": "add", "validator": bad, "epoch_height": 20})).unwrap_err();
+        assert!(matches!(err, CtxError::BadArgs(_)), "{}", err);
+        let err = dispatch_mut(&mut ctx, &addr_of(&set[0]), NS, "propose", &json!({"action": "remove", "pk": set[0].pk, "epoch_height": 20})).unwrap_err();
+        assert_eq!(err.to_string(), "cannot remove the last validator");
+        assert_eq!(jail(&mut ctx, &set[0].pk).unwrap_err().to_string(), "cannot remove the last validator");
+        assert!(ctx.ns(NS).unwrap().get("next_proposal").is_none());
+    }
+
+    #[test]
+    fn jail_removes_the_validator_from_the_next_block() {
+        let set: Vec<Validator> = (0..3).map(validator).collect();
+        let mut ctx = setup(&set);
+        assert_eq!(jail(&mut ctx, &set[1].pk.to_uppercase()).unwrap(), 11);
+        assert_eq!(ids(set_at(&ctx, 10)), ["v0", "v1", "v2"]);
+        assert_eq!(ids(set_at(&ctx, 11)), ["v0", "v2"]);
+        assert_eq!(jail(&mut ctx, &set[1].pk).unwrap_err().to_string(), "not a validator");
+    }
+
+    #[test]
+    fn jail_does_not_wait_for_a_scheduled_change() {
+        let set: Vec<Validator> = (0..3).map(validator).collect();
+        let mut ctx = setup(&set);
+        let mut schedule = epochs(&ctx);
+        schedule.push(Epoch { from_height: 30, validators: set[..2].to_vec() });
+        ctx.ns_mut(NS).insert("epochs".into(), serde_json::to_value(schedule).unwrap());
+
+        assert_eq!(jail(&mut ctx, &set[1].pk).unwrap(), 11);
+        assert_eq!(ids(set_at(&ctx, 11)), ["v0", "v2"]);
+        assert_eq!(ids(set_at(&ctx, 30)), ["v0"]);
+        let heights: Vec<u64> = epochs(&ctx).iter().map(|e| e.from_height).collect();
+        assert_eq!(heights, [0, 11, 30]);
+    }
+}
@@ -6,3 +6,7 @@ pub mod storage;
 pub mod pq;
 pub mod security;
 pub mod consensus;
+pub mod merkle;
+pub mod blocktree;
+pub mod mempool;
+pub mod sync;
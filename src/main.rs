@@ -1,20 +1,36 @@
-mod util;
-mod types;
-mod contracts;
-mod runtime;
-mod storage;
-mod pq;
-mod security;
-mod consensus;
-
 use actix_web::{App, HttpServer, get, post, web, Responder, HttpResponse};
 use actix_web::rt::{spawn, time};
-use runtime::{new_shared, SharedRuntime};
+use qsc_rs_simple_contracts::{consensus, merkle, pq, security, sync, types, util};
+use qsc_rs_simple_contracts::runtime::{new_shared, SharedRuntime};
 use types::RpcCall;
 
 #[get("/head")]
 async fn head(rt: web::Data<SharedRuntime>) -> impl Responder {
-    web::Json(rt.head())
+    // The latest block, plus how far the chain is final.
+    let mut v = serde_json::to_value(rt.head()).unwrap();
+    let finalized = rt.finalized_height();
+    v["finalized_height"] = finalized.into();
+    v["finalized_hash"] = rt.block(finalized).map(|b| b.hash).into();
+    web::Json(v)
+}
+
+#[get("/finality")]
+async fn finality(rt: web::Data<SharedRuntime>) -> impl Responder {
+    match rt.latest_cert() {
+        Some(c) => HttpResponse::Ok().json(c),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/block/{n}/finality")]
+async fn block_finality(rt: web::Data<SharedRuntime>, path: web::Path<u64>) -> impl Responder {
+    let n = path.into_inner();
+    if rt.block(n).is_none() { return HttpResponse::NotFound().finish(); }
+    HttpResponse::Ok().json(serde_json::json!({
+        "height": n,
+        "finalized": n <= rt.finalized_height(),
+        "cert": rt.cert(n)
+    }))
 }
 
 #[get("/block/{n}")]
@@ -25,6 +41,66 @@ async fn block(rt: web::Data<SharedRuntime>, path: web::Path<u64>) -> impl Respo
     }
 }
 
+#[get("/blocks")]
+async fn blocks(rt: web::Data<SharedRuntime>, q: web::Query<std::collections::HashMap<String,String>>) -> impl Responder {
+    let from: u64 = q.get("from").and_then(|s| s.parse().ok()).unwrap_or(0);
+    let to: u64 = q.get("to").and_then(|s| s.parse().ok()).unwrap_or(from);
+    let to = to.min(from.saturating_add(sync::BATCH - 1));
+    HttpResponse::Ok().json(rt.blocks(from, to))
+}
+
+#[get("/block/{n}/proof/{tx_hash}")]
+async fn tx_proof(rt: web::Data<SharedRuntime>, path: web::Path<(u64, String)>) -> impl Responder {
+    let (n, tx_hash) = path.into_inner();
+    match rt.tx_proof(n, &tx_hash) {
+        Some(p) => HttpResponse::Ok().json(p),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[post("/block/{n}/proof/verify")]
+async fn tx_proof_verify(rt: web::Data<SharedRuntime>, path: web::Path<u64>, body: web::Json<merkle::MerkleProof>) -> impl Responder {
+    match rt.block(path.into_inner()) {
+        Some(b) => HttpResponse::Ok().json(serde_json::json!({
+            "valid": merkle::verify_proof(&body.leaf, &body, &b.tx_root)
+        })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/tx/{hash}")]
+async fn tx(rt: web::Data<SharedRuntime>, path: web::Path<String>) -> impl Responder {
+    match rt.tx(&path.into_inner()) {
+        Some((tx, loc)) => HttpResponse::Ok().json(serde_json::json!({
+            "tx": tx,
+            "height": loc.height,
+            "index": loc.index
+        })),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+#[get("/account/{addr}/txs")]
+async fn account_txs(rt: web::Data<SharedRuntime>, path: web::Path<String>, q: web::Query<std::collections::HashMap<String,String>>) -> impl Responder {
+    let offset: usize = q.get("offset").and_then(|s| s.parse().ok()).unwrap_or(0);
+    let limit: usize = q.get("limit").and_then(|s| s.parse().ok()).unwrap_or(50).min(500);
+    let (txs, total) = rt.account_txs(&path.into_inner(), offset, limit);
+    HttpResponse::Ok().json(serde_json::json!({
+        "total": total,
+        "offset": offset,
+        "limit": limit,
+        "txs": txs
+    }))
+}
+
+#[get("/tx/{hash}/receipt")]
+async fn tx_receipt(rt: web::Data<SharedRuntime>, path: web::Path<String>) -> impl Responder {
+    match rt.receipt(&path.into_inner()) {
+        Some(r) => HttpResponse::Ok().json(r),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
 #[get("/validator")]
 async fn validator(rt: web::Data<SharedRuntime>) -> impl Responder {
     let (alg, pk) = rt.validator_info();
@@ -35,29 +111,24 @@ async fn validator(rt: web::Data<SharedRuntime>) -> impl Responder {
 async fn chain(rt: web::Data<SharedRuntime>) -> impl Responder {
     HttpResponse::Ok().json(serde_json::json!({
         "chain_id": rt.chain_id,
-        "hash_alg": match crate::util::current_hash_alg() { crate::util::HashAlg::Blake2b512 => "blake2b-512", crate::util::HashAlg::Sha3_512 => "sha3-512" },
-        "sig_algs_allowed": crate::security::allowed_sig_algs(),
+        "hash_alg": match util::current_hash_alg() { util::HashAlg::Blake2b512 => "blake2b-512", util::HashAlg::Sha3_512 => "sha3-512" },
+        "sig_algs_allowed": security::allowed_sig_algs(),
         "validator": { "alg": "mldsa3", "pk": rt.validator_info().1 }
     }))
 }
 
 #[get("/nonce/{addr}")]
 async fn nonce(rt: web::Data<SharedRuntime>, path: web::Path<String>) -> impl Responder {
-    let n = rt.next_nonce(&path.into_inner());
-    HttpResponse::Ok().json(serde_json::json!({ "next_nonce": n }))
+    let addr = path.into_inner();
+    HttpResponse::Ok().json(serde_json::json!({
+        "next_nonce": rt.next_nonce(&addr),
+        "pending_nonce": rt.pending_nonce(&addr)
+    }))
 }
 
 #[post("/canonical")]
 async fn canonical(body: web::Json<RpcCall>) -> impl Responder {
-    let payload = serde_json::json!({
-        "from": body.from,
-        "nonce": body.nonce,
-        "chain_id": body.chain_id,
-        "contract": body.contract,
-        "method": body.method,
-        "args": body.args,
-    });
-    let s = serde_json::to_string(&payload).unwrap();
+    let s = String::from_utf8(body.canonical_payload()).unwrap();
     HttpResponse::Ok().json(serde_json::json!({ "payload": s }))
 }
 
@@ -72,7 +143,7 @@ async fn call(rt: web::Data<SharedRuntime>, body: web::Json<RpcCall>) -> impl Re
         }));
     }
 
-    let allowed = crate::security::allowed_sig_algs();
+    let allowed = security::allowed_sig_algs();
     if !allowed.iter().any(|a| a == &body.alg.to_lowercase()) {
         return HttpResponse::BadRequest().json(serde_json::json!({
             "error":"signature algorithm not allowed",
@@ -86,64 +157,26 @@ async fn call(rt: web::Data<SharedRuntime>, body: web::Json<RpcCall>) -> impl Re
             "expected": rt.chain_id
         }));
     }
-    let expected = rt.next_nonce(&body.from);
-    if body.nonce != expected {
-        return HttpResponse::BadRequest().json(serde_json::json!({
-            "error":"bad nonce",
-            "expected": expected
-        }));
-    }
-
-    let payload = serde_json::json!({
-        "from": body.from,
-        "nonce": body.nonce,
-        "chain_id": body.chain_id,
-        "contract": body.contract,
-        "method": body.method,
-        "args": body.args,
-    });
-    let payload_bytes = serde_json::to_vec(&payload).unwrap();
-
-    let ok = match body.alg.as_str() {
-        "mldsa3" => {
-            let sig = hex::decode(&body.sig).unwrap_or_default();
-            pq::verify_mldsa3(&payload_bytes, &sig, &pk_bytes)
-        }
-        _ => false
-    };
-    if !ok {
+    let payload_bytes = body.canonical_payload();
+    if !security::verify_sig(&body.alg, &pk_bytes, &body.sig, &payload_bytes) {
         return HttpResponse::BadRequest().json(serde_json::json!({"error":"invalid PQ signature"}));
     }
 
-    if body.contract == "token" && body.method == "mint" {
-        let (_alg, vpk_hex) = rt.validator_info();
-        let vpk = hex::decode(&vpk_hex).unwrap_or_default();
-        let default_minter = pq::address_from_pk(&vpk);
-        let env_minter = std::env::var("QSC_MINTER_ADDR").ok();
-        let minter = env_minter.as_deref().unwrap_or(&default_minter);
-        if body.from != minter {
-            return HttpResponse::BadRequest().json(serde_json::json!({
-                "error":"mint not allowed for this sender",
-                "required_minter": minter
-            }));
-        }
-    }
-
-    let tx_hash = crate::util::hash_hex(&payload_bytes);
+    let pending = body.tx();
+    let tx_hash = pending.tx_hash.clone();
 
-    rt.submit(types::Call {
-        from: body.from.clone(),
-        contract: body.contract.clone(),
-        method: body.method.clone(),
-        args: body.args.clone(),
-    }, types::Auth {
-        alg: body.alg.clone(),
-        pk: body.pk.clone(),
-        sig: body.sig.clone(),
-    }, body.nonce, body.chain_id.clone(), tx_hash);
+    let submitted = rt.submit(pending);
+    if let Err(e) = submitted {
+        return HttpResponse::BadRequest().json(serde_json::json!({
+            "error": e,
+            "next_nonce": rt.next_nonce(&body.from),
+            "pending_nonce": rt.pending_nonce(&body.from)
+        }));
+    }
 
     HttpResponse::Ok().json(serde_json::json!({
         "status":"enqueued",
+        "tx_hash": tx_hash,
         "will_apply_in_next_block": true
     }))
 }
@@ -161,11 +194,15 @@ async fn query(rt: web::Data<SharedRuntime>, q: web::Query<std::collections::Has
 }
 
 #[get("/consensus/config")]
-async fn consensus_config() -> impl Responder {
-    match crate::consensus::PoAConfig::from_env() {
+async fn consensus_config(rt: web::Data<SharedRuntime>) -> impl Responder {
+    // Validators that govern the next block, per the `validators` contract.
+    match rt.poa_config(&rt.last_block()) {
         Some(cfg) => HttpResponse::Ok().json(serde_json::json!({
             "validators": cfg.validators,
-            "slot_ms": cfg.slot_ms
+            "stakes": cfg.stakes,
+            "slot_ms": cfg.slot_ms,
+            "genesis_ms": cfg.genesis_ms,
+            "current_slot": cfg.slot_at(util::now_ms())
         })),
         None => HttpResponse::BadRequest().json(serde_json::json!({"error":"no PoA config (set QSC_VALIDATORS_JSON)"}))
     }
@@ -173,41 +210,93 @@ async fn consensus_config() -> impl Responder {
 
 #[post("/consensus/commit")]
 async fn consensus_commit(rt: web::Data<SharedRuntime>, body: web::Json<types::Block>) -> impl Responder {
-    let cfg = match crate::consensus::PoAConfig::from_env() {
+    let cfg = match consensus::PoAConfig::from_env() {
         Some(c) => c, None => return HttpResponse::BadRequest().json(serde_json::json!({"error":"no PoA config"}))
     };
-    let parent = rt.last_block();
-    if let Err(e) = crate::consensus::verify_block_poa(&cfg, &parent.hash, &body) {
+    // The parent may be our head or a recent block on a competing branch.
+    let parent = match rt.known_block(&body.parent) {
+        Some(p) => p,
+        None => {
+            let last = rt.last_block();
+            if body.height <= last.height {
+                return HttpResponse::BadRequest().json(serde_json::json!({"error": "unknown parent"}));
+            }
+            // We missed something: catch up from peers instead of rejecting forever.
+            spawn(sync::sync_with_peers(rt.get_ref().clone(), cfg));
+            return HttpResponse::Conflict().json(serde_json::json!({
+                "error": "out of sync, catching up",
+                "height": last.height
+            }));
+        }
+    };
+    let cfg = rt.poa_config(&parent).unwrap_or(cfg);
+    if let Err(e) = consensus::verify_block_poa(&cfg, &rt.chain_id, &parent, &body) {
         return HttpResponse::BadRequest().json(serde_json::json!({"error": e}));
     }
     match rt.apply_external_block(body.into_inner()) {
-        Ok(b) => HttpResponse::Ok().json(serde_json::json!({"ok": true, "height": b.height})),
+        Ok(b) => {
+            if let Some(p) = rt.precommit(&b) {
+                let me = rt.validator_pk_hex();
+                spawn(async move { sync::broadcast_precommit(&reqwest::Client::new(), &cfg, &me, &p).await });
+            }
+            HttpResponse::Ok().json(serde_json::json!({"ok": true, "height": b.height}))
+        }
+        Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"ok": false, "error": e}))
+    }
+}
+
+#[get("/evidence")]
+async fn evidence(rt: web::Data<SharedRuntime>) -> impl Responder {
+    web::Json(rt.evidence())
+}
+
+#[post("/consensus/precommit")]
+async fn consensus_precommit(rt: web::Data<SharedRuntime>, body: web::Json<types::Precommit>) -> impl Responder {
+    match rt.add_precommit(body.into_inner()) {
+        Ok(()) => HttpResponse::Ok().json(serde_json::json!({"ok": true, "finalized_height": rt.finalized_height()})),
         Err(e) => HttpResponse::BadRequest().json(serde_json::json!({"ok": false, "error": e}))
     }
 }
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    let rt = new_shared();
+    let rt = new_shared()?;
     let rt_bg = rt.clone();
 
     let consensus_mode = std::env::var("QSC_CONSENSUS").unwrap_or_else(|_| "local".into());
-    if consensus_mode.to_lowercase() == "poa" {
+    // `pos` runs the same slot loop; only leader selection differs.
+    if matches!(consensus_mode.to_lowercase().as_str(), "poa" | "pos") {
         let rt_loop = rt.clone();
         spawn(async move {
-            let cfg = match crate::consensus::PoAConfig::from_env() { Some(c) => c, None => return };
+            let cfg = match consensus::PoAConfig::from_env() { Some(c) => c, None => return };
             let client = reqwest::Client::new();
+            sync::sync_with_peers(rt_loop.clone(), cfg.clone()).await;
             loop {
-                time::sleep(std::time::Duration::from_millis(cfg.slot_ms)).await;
+                // Wake up at the start of each slot and produce if it is ours.
+                let now = util::now_ms();
+                let slot = cfg.slot_at(now) + 1;
+                let wait = cfg.slot_start(slot).saturating_sub(now) as u64;
+                time::sleep(std::time::Duration::from_millis(wait)).await;
                 let head_blk = rt_loop.last_block();
-                let next_h = head_blk.height + 1;
-                let expected = cfg.expected_leader(next_h);
-                if expected.pk.eq_ignore_ascii_case(&rt_loop.validator_pk_hex()) && rt_loop.has_sk() {
-                    let blk = rt_loop.produce_block();
-                    for v in &cfg.validators {
+                // The validator set may change at an epoch height.
+                let cfg = rt_loop.poa_config(&head_blk).unwrap_or_else(|| cfg.clone());
+                let expected = cfg.leader(&head_blk, slot);
+                if head_blk.slot < slot && expected.pk.eq_ignore_ascii_case(&rt_loop.validator_pk_hex()) && rt_loop.has_sk() {
+                    let blk = rt_loop.produce_block(slot);
+                    for v in &cfg.peers {
                         if v.pk.eq_ignore_ascii_case(&rt_loop.validator_pk_hex()) { continue; }
                         let url = format!("{}/consensus/commit", v.url.trim_end_matches('/'));
-                        let _ = client.post(&url).json(&blk).send().await;
+                        match client.post(&url).json(&blk).send().await {
+                            Ok(r) if !r.status().is_success() => {
+                                let body = r.text().await.unwrap_or_default();
+                                eprintln!("commit of block {} to {} rejected: {}", blk.height, v.id, body);
+                            }
+                            Err(e) => eprintln!("commit of block {} to {} failed: {}", blk.height, v.id, e),
+                            Ok(_) => {}
+                        }
+                    }
+                    if let Some(p) = rt_loop.precommit(&blk) {
+                        sync::broadcast_precommit(&client, &cfg, &rt_loop.validator_pk_hex(), &p).await;
                     }
                 }
             }
@@ -216,7 +305,10 @@ async fn main() -> std::io::Result<()> {
         spawn(async move {
             loop {
                 time::sleep(std::time::Duration::from_secs(3)).await;
-                let b = rt_bg.produce_block();
+                let slot = rt_bg.last_block().slot + 1;
+                let b = rt_bg.produce_block(slot);
+                // A lone node is the whole validator set, so its own vote finalizes.
+                rt_bg.precommit(&b);
                 println!("Produced block {} (txs: {})", b.height, b.txs.len());
             }
         });
@@ -227,6 +319,12 @@ async fn main() -> std::io::Result<()> {
             .app_data(web::Data::new(rt.clone()))
             .service(head)
             .service(block)
+            .service(blocks)
+            .service(tx_proof)
+            .service(tx_proof_verify)
+            .service(tx)
+            .service(account_txs)
+            .service(tx_receipt)
             .service(validator)
             .service(chain)
             .service(nonce)
@@ -235,8 +333,69 @@ async fn main() -> std::io::Result<()> {
             .service(query)
             .service(consensus_config)
             .service(consensus_commit)
+            .service(consensus_precommit)
+            .service(evidence)
+            .service(finality)
+            .service(block_finality)
     })
     .bind(("0.0.0.0", 8000))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test;
+    use qsc_rs_simple_contracts::runtime::open_runtime;
+    use qsc_rs_simple_contracts::storage::kv::KvStorage;
+    use serde_json::json;
+
+    fn open(name: &str, sk: &[u8], pk: &[u8]) -> (SharedRuntime, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("qsc-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let rt = open_runtime(Box::new(KvStorage::open(&dir).unwrap()), "test".into(), sk.to_vec(), pk.to_vec()).unwrap();
+        (rt, dir)
+    }
+
+    #[actix_web::test]
+    async fn receipt_endpoint_serves_canonical_receipts_only() {
+        let (sk, pk) = pq::keygen_mldsa3();
+        let from = pq::address_from_pk(&pk);
+        let (a, dir_a) = open("receipt-a", &sk, &pk);
+        let (b, dir_b) = open("receipt-b", &sk, &pk);
+        let mut rpc = RpcCall {
+            from: from.clone(),
+            contract: "token".into(),
+            method: "mint".into(),
+            args: json!({"to": from, "amount": 3}),
+            alg: "mldsa3".into(),
+            pk: hex::encode(&pk),
+            sig: String::new(),
+            nonce: 0,
+            fee: 0,
+            gas_limit: types::DEFAULT_GAS_LIMIT,
+            chain_id: "test".into(),
+        };
+        rpc.sig = hex::encode(pq::sign_mldsa3(&rpc.canonical_payload(), &sk));
+        let pending = rpc.tx();
+        a.submit(pending.clone()).unwrap();
+        a.produce_block(1);
+
+        let app = test::init_service(App::new().app_data(web::Data::new(a.clone())).service(tx_receipt)).await;
+        let uri = format!("/tx/{}/receipt", pending.tx_hash);
+        let r: serde_json::Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!((&r["height"], &r["status"], &r["result"]["event"]), (&json!(1), &json!("success"), &json!("Mint")));
+
+        // A longer branch from later slots orphans block 1 and its receipt.
+        for slot in [2, 3] { a.apply_external_block(b.produce_block(slot)).unwrap(); }
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(resp.status(), 404);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/tx/unknown/receipt").to_request()).await;
+        assert_eq!(resp.status(), 404);
+
+        drop((app, a, b));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+}
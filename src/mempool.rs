@@ -0,0 +1,139 @@
+use crate::types::Tx;
+use std::collections::HashMap;
+
+/// Pending transactions in arrival order. A sender may queue several txs
+/// ahead of its committed nonce; only gap-free runs are handed to blocks.
+#[derive(Default)]
+pub struct Mempool {
+    txs: Vec<Tx>,
+}
+
+fn env_usize(key: &str, default: usize) -> usize {
+    std::env::var(key).ok().and_then(|s| s.parse().ok()).unwrap_or(default)
+}
+
+impl Mempool {
+    pub fn len(&self) -> usize { self.txs.len() }
+    pub fn is_empty(&self) -> bool { self.txs.is_empty() }
+
+    /// Next nonce a sender should sign: the committed nonce plus every pending
+    /// tx that extends it without a gap.
+    pub fn pending_nonce(&self, addr: &str, committed: u64) -> u64 {
+        let mut next = committed;
+        while self.txs.iter().any(|t| t.call.from == addr && t.nonce == next) {
+            next += 1;
+        }
+        next
+    }
+
+    /// Accepts nonces in `[committed, committed + QSC_MAX_NONCE_GAP)` that are
+    /// not already pending for the sender. A gap below 1 is treated as 1, so
+    /// the committed nonce itself is always accepted.
+    pub fn insert(&mut self, tx: Tx, committed: u64) -> Result<(), String> {
+        let max_pending = env_usize("QSC_MAX_PENDING_PER_ADDR", 100);
+        let max_gap = env_usize("QSC_MAX_NONCE_GAP", 16).max(1) as u64;
+        let from = &tx.call.from;
+
+        if tx.nonce < committed {
+            return Err(format!("nonce too low: committed nonce is {}", committed));
+        }
+        if tx.nonce >= committed.saturating_add(max_gap) {
+            return Err(format!("nonce too far ahead: max accepted is {}", committed.saturating_add(max_gap - 1)));
+        }
+        let mut count = 0;
+        for t in self.txs.iter().filter(|t| &t.call.from == from) {
+            if t.nonce == tx.nonce { return Err(format!("nonce {} already pending", tx.nonce)); }
+            count += 1;
+        }
+        if count >= max_pending {
+            return Err(format!("rate-limit: {} pending txs from sender", count));
+        }
+        self.txs.push(tx);
+        Ok(())
+    }
+
+    /// Removes up to `max` executable txs: each sender's txs come out in nonce
+    /// order starting at its committed nonce. Among the senders' next txs the
+    /// highest fee goes first, ties in arrival order.
+    pub fn drain_ready(&mut self, nonces: &HashMap<String, u64>, max: usize) -> Vec<Tx> {
+        let mut expected: HashMap<String, u64> = HashMap::new();
+        let mut taken = vec![false; self.txs.len()];
+        let mut out = Vec::new();
+        while out.len() < max {
+            let mut best: Option<usize> = None;
+            for (i, tx) in self.txs.iter().enumerate() {
+                if taken[i] { continue; }
+                let next = *expected.entry(tx.call.from.clone())
+                    .or_insert_with(|| *nonces.get(&tx.call.from).unwrap_or(&0));
+                if tx.nonce == next && best.is_none_or(|b| tx.fee > self.txs[b].fee) {
+                    best = Some(i);
+                }
+            }
+            let Some(i) = best else { break };
+            let tx = &self.txs[i];
+            *expected.get_mut(&tx.call.from).unwrap() += 1;
+            taken[i] = true;
+            out.push(tx.clone());
+        }
+        let mut i = 0;
+        self.txs.retain(|_| { i += 1; !taken[i - 1] });
+        out
+    }
+
+    /// Drops txs whose nonce has already been used on chain.
+    pub fn prune(&mut self, nonces: &HashMap<String, u64>) {
+        self.txs.retain(|t| t.nonce >= *nonces.get(&t.call.from).unwrap_or(&0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Auth, Call};
+
+    fn tx(from: &str, nonce: u64, fee: u64) -> Tx {
+        Tx {
+            call: Call { from: from.into(), contract: "token".into(), method: "transfer".into(), args: serde_json::json!({}) },
+            timestamp: 0,
+            auth: Auth { alg: "mldsa3".into(), pk: String::new(), sig: String::new() },
+            nonce, fee, gas_limit: crate::types::DEFAULT_GAS_LIMIT,
+            chain_id: "test".into(),
+            tx_hash: format!("{}:{}", from, nonce),
+        }
+    }
+
+    fn order(txs: &[Tx]) -> Vec<String> { txs.iter().map(|t| t.tx_hash.clone()).collect() }
+
+    #[test]
+    fn insert_checks_nonces_and_pending_nonce_follows_the_queue() {
+        let mut pool = Mempool::default();
+        assert_eq!(pool.insert(tx("a", 2, 0), 3).unwrap_err(), "nonce too low: committed nonce is 3");
+        assert_eq!(pool.insert(tx("a", 19, 0), 3).unwrap_err(), "nonce too far ahead: max accepted is 18");
+        pool.insert(tx("a", 3, 0), 3).unwrap();
+        assert_eq!(pool.insert(tx("a", 3, 5), 3).unwrap_err(), "nonce 3 already pending");
+        // Another sender may use the same nonce.
+        pool.insert(tx("b", 3, 0), 3).unwrap();
+
+        assert_eq!(pool.pending_nonce("a", 3), 4);
+        pool.insert(tx("a", 5, 0), 3).unwrap();
+        assert_eq!(pool.pending_nonce("a", 3), 4);
+        pool.insert(tx("a", 4, 0), 3).unwrap();
+        assert_eq!(pool.pending_nonce("a", 3), 6);
+        assert_eq!(pool.pending_nonce("c", 7), 7);
+    }
+
+    #[test]
+    fn drain_ready_keeps_nonce_order_and_prefers_fees_across_senders() {
+        let mut pool = Mempool::default();
+        // a pays more for its later tx, which must still wait for nonce 0.
+        for t in [tx("a", 1, 9), tx("a", 0, 1), tx("b", 0, 5), tx("b", 1, 5), tx("c", 2, 9)] {
+            pool.insert(t, 0).unwrap();
+        }
+        let nonces = HashMap::from([("c".to_string(), 1)]);
+        assert_eq!(order(&pool.drain_ready(&nonces, 3)), ["b:0", "b:1", "a:0"]);
+        // Once those are committed; c:2 still waits for nonce 1.
+        let nonces = HashMap::from([("a".to_string(), 1), ("b".to_string(), 2), ("c".to_string(), 1)]);
+        assert_eq!(order(&pool.drain_ready(&nonces, 10)), ["a:1"]);
+        assert_eq!(order(&pool.txs), ["c:2"]);
+    }
+}
@@ -0,0 +1,100 @@
+use serde::{Serialize, Deserialize};
+use crate::util::hash_hex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProofStep {
+    pub hash: String,
+    /// true if the sibling sits on the left of the running hash
+    pub left: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    pub leaf: String,
+    pub index: usize,
+    pub steps: Vec<ProofStep>,
+    pub root: String,
+}
+
+pub fn empty_root() -> String { "0".repeat(128) }
+
+// Leaves and inner nodes use different prefixes so an inner node can never be
+// presented as a leaf.
+fn hash_leaf(leaf: &str) -> String {
+    hash_hex(format!("\x00{}", leaf).as_bytes())
+}
+fn hash_node(l: &str, r: &str) -> String {
+    hash_hex(format!("\x01{}{}", l, r).as_bytes())
+}
+
+fn next_level(level: &[String]) -> Vec<String> {
+    // An odd trailing node is carried up unchanged instead of being duplicated.
+    level.chunks(2)
+        .map(|pair| if pair.len() == 2 { hash_node(&pair[0], &pair[1]) } else { pair[0].clone() })
+        .collect()
+}
+
+pub fn merkle_root(leaves: &[String]) -> String {
+    if leaves.is_empty() { return empty_root(); }
+    let mut level: Vec<String> = leaves.iter().map(|l| hash_leaf(l)).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+    level.remove(0)
+}
+
+pub fn merkle_proof(leaves: &[String], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() { return None; }
+    let mut level: Vec<String> = leaves.iter().map(|l| hash_leaf(l)).collect();
+    let mut pos = index;
+    let mut steps = Vec::new();
+    while level.len() > 1 {
+        let sibling = pos ^ 1;
+        if sibling < level.len() {
+            steps.push(ProofStep { hash: level[sibling].clone(), left: sibling < pos });
+        }
+        level = next_level(&level);
+        pos /= 2;
+    }
+    Some(MerkleProof { leaf: leaves[index].clone(), index, steps, root: level.remove(0) })
+}
+
+pub fn verify_proof(leaf: &str, proof: &MerkleProof, root: &str) -> bool {
+    let mut acc = hash_leaf(leaf);
+    for step in &proof.steps {
+        acc = if step.left { hash_node(&step.hash, &acc) } else { hash_node(&acc, &step.hash) };
+    }
+    acc == root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(n: usize) -> Vec<String> {
+        (0..n).map(|i| hash_hex(format!("tx{}", i).as_bytes())).collect()
+    }
+
+    #[test]
+    fn proofs_round_trip_for_even_and_odd_leaf_counts() {
+        for n in 1..=7 {
+            let leaves = leaves(n);
+            let root = merkle_root(&leaves);
+            for (i, leaf) in leaves.iter().enumerate() {
+                let proof = merkle_proof(&leaves, i).unwrap();
+                assert_eq!(proof.root, root);
+                assert!(verify_proof(leaf, &proof, &root), "n={} i={}", n, i);
+            }
+            assert!(merkle_proof(&leaves, n).is_none());
+        }
+    }
+
+    #[test]
+    fn proof_rejects_other_leaf_or_root() {
+        let leaves = leaves(5);
+        let root = merkle_root(&leaves);
+        let proof = merkle_proof(&leaves, 4).unwrap();
+        assert!(!verify_proof(&leaves[3], &proof, &root));
+        assert!(!verify_proof(&leaves[4], &proof, &merkle_root(&leaves[..4])));
+    }
+}
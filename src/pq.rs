@@ -19,6 +19,10 @@ pub fn verify_mldsa3(msg: &[u8], sig: &[u8], pk: &[u8]) -> bool {
     verify_detached_signature(&sig, msg, &pk).is_ok()
 }
 
+pub fn is_mldsa3_pk(pk: &[u8]) -> bool {
+    PublicKey::from_bytes(pk).is_ok()
+}
+
 pub fn address_from_pk(pk: &[u8]) -> String {
     hash_hex(pk)
 }
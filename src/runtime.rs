@@ -1,66 +1,81 @@
-use crate::contracts::{self, Ctx};
+use crate::blocktree::{self, BlockTree, Snapshot};
+use crate::consensus::{self, PoAConfig};
+use crate::contracts::{self, staking, token, validators, Ctx};
+use crate::mempool::Mempool;
+use crate::merkle::{self, MerkleProof};
 use crate::pq;
-use crate::storage;
-use crate::types::{Auth, Block, Call, Tx};
-use crate::util::{hash_hex, now_ms};
+use crate::security;
+use crate::storage::{self, Storage, TxLocation};
+use crate::types::{Block, Call, Evidence, FinalityCert, Precommit, Receipt, SignedHeader, Tx, TxStatus};
+use crate::util::now_ms;
 
 use parking_lot::Mutex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::sync::Arc;
 
 pub type SharedRuntime = Arc<Runtime>;
 
 pub struct Runtime {
     pub ctx: Mutex<Ctx>,
-    pub mempool: Mutex<Vec<Tx>>,
+    pub mempool: Mutex<Mempool>,
     pub chain: Mutex<Vec<Block>>,
     pub nonces: Mutex<HashMap<String, u64>>,
+    pub receipts: Mutex<HashMap<String, Receipt>>,
+    pub tree: Mutex<BlockTree>,
+    /// Precommits not yet part of a certificate, by (height, block hash).
+    pub votes: Mutex<HashMap<(u64, String), Vec<Precommit>>>,
+    /// Finality certificates of canonical blocks, by height.
+    pub certs: Mutex<BTreeMap<u64, FinalityCert>>,
+    /// Highest height this node has precommitted; it never votes twice at a height.
+    pub last_vote: Mutex<u64>,
+    /// First signed header seen per (validator pk, slot), to spot equivocation.
+    pub headers: Mutex<HashMap<(String, u64), SignedHeader>>,
+    /// Conflicting header pairs seen so far, ready for an `evidence` tx.
+    pub evidence: Mutex<Vec<Evidence>>,
+    /// Held from executing a block until it is committed, and across reorgs,
+    /// so production and imports never commit state built on a stale head.
+    import: Mutex<()>,
+    pub store: Box<dyn Storage>,
     pub chain_id: String,
     pub validator_sk: Vec<u8>,
     pub validator_pk: Vec<u8>,
 }
 
 impl Runtime {
-    pub fn produce_block(&self) -> Block {
+    pub fn produce_block(&self, slot: u64) -> Block {
+        let _import = self.import.lock();
         let parent = self.chain.lock().last().cloned().expect("genesis must exist");
         let height = parent.height + 1;
         let timestamp = now_ms();
 
         let txs = {
-            let mut pool = self.mempool.lock();
+            let nonces = self.nonces.lock().clone();
             let max_block: usize = std::env::var("QSC_MAX_TX_PER_BLOCK").ok().and_then(|s| s.parse().ok()).unwrap_or(100);
-            let take = std::cmp::min(max_block, pool.len());
-            pool.drain(..take).collect::<Vec<_>>()
+            self.mempool.lock().drain_ready(&nonces, max_block)
         };
 
-        for tx in &txs {
-            let _ = self.dispatch_mut(&tx.call);
-            self.inc_nonce(&tx.call.from);
+        // Txs that fail the execution-time checks are left out of the block.
+        let exec = self.execute(height, &pq::address_from_pk(&self.validator_pk), &txs);
+        for (tx_hash, reason) in &exec.rejected {
+            eprintln!("skipping tx {}: {}", tx_hash, reason);
         }
 
-        let header_str = format!("{}|{}|{}|{}", parent.hash, height, txs.len(), timestamp);
-        let hash = hash_hex(header_str.as_bytes());
-
-        let sig = pq::sign_mldsa3(hash.as_bytes(), &self.validator_sk);
-        let sig_hex = hex::encode(sig);
-        let pk_hex = hex::encode(&self.validator_pk);
-
-        let block = Block {
+        let mut block = Block {
             height,
+            slot,
             parent: parent.hash.clone(),
-            hash,
+            hash: String::new(),
             timestamp,
-            validator_pk: pk_hex,
-            validator_sig: sig_hex,
-            txs,
+            tx_root: String::new(),
+            state_root: exec.ctx.state_root(),
+            receipts_root: Receipt::root(&exec.receipts),
+            validator_pk: hex::encode(&self.validator_pk),
+            validator_sig: String::new(),
+            gas_used: exec.gas_used(),
+            txs: exec.txs.clone(),
         };
-
-        {
-            let mut chain = self.chain.lock();
-            chain.push(block.clone());
-        }
-        let _ = storage::append_block(&block);
-        let _ = storage::snapshot_state(&self.ctx.lock(), block.height);
+        self.seal(&mut block);
+        self.commit(&block, exec, true);
 
         block
     }
@@ -70,34 +85,390 @@ impl Runtime {
     }
     pub fn head(&self) -> Block { self.last_block() }
     pub fn block(&self, n: u64) -> Option<Block> { self.chain.lock().get(n as usize).cloned() }
+    /// Blocks `from..=to` that we have, in height order.
+    pub fn blocks(&self, from: u64, to: u64) -> Vec<Block> {
+        let chain = self.chain.lock();
+        if from > to || from as usize >= chain.len() { return Vec::new(); }
+        let end = (to as usize).min(chain.len() - 1);
+        chain[from as usize..=end].to_vec()
+    }
 
-    pub fn apply_external_block(&self, block: Block) -> Result<Block, String> {
-        let parent = self.chain.lock().last().cloned().ok_or("no head")?;
+    fn seal(&self, block: &mut Block) {
+        block.tx_root = block.compute_tx_root();
+        block.hash = block.compute_hash();
+        let sig = pq::sign_mldsa3(&SignedHeader::payload(&self.chain_id, &block.hash), &self.validator_sk);
+        block.validator_sig = hex::encode(sig);
+    }
+
+    pub fn tx_proof(&self, height: u64, tx_hash: &str) -> Option<MerkleProof> {
+        let block = self.block(height)?;
+        let leaves: Vec<String> = block.txs.iter().map(|t| t.tx_hash.clone()).collect();
+        let index = leaves.iter().position(|h| h == tx_hash)?;
+        merkle::merkle_proof(&leaves, index)
+    }
+
+    /// Structural checks that do not depend on local state.
+    fn check_header(parent: &Block, block: &Block) -> Result<(), String> {
         if block.parent != parent.hash { return Err("parent mismatch".into()); }
         if block.height != parent.height + 1 { return Err("height mismatch".into()); }
+        if block.tx_root != block.compute_tx_root() { return Err("tx_root mismatch".into()); }
+        if block.hash != block.compute_hash() { return Err("header hash mismatch".into()); }
+        Ok(())
+    }
+
+    /// Executes txs against working copies of the head state and nonces;
+    /// nothing is visible to the node until `commit`.
+    fn execute(&self, height: u64, beneficiary: &str, txs: &[Tx]) -> Execution {
+        let mut ctx = self.ctx.lock().clone();
+        let mut nonces = self.nonces.lock().clone();
+        let receipts = self.receipts.lock();
+        let (txs, receipts, rejected) =
+            execute_txs(&mut ctx, &mut nonces, &self.chain_id, height, beneficiary, txs, |h| receipts.contains_key(h));
+        Execution { ctx, nonces, txs, receipts, rejected }
+    }
 
+    /// Executes an incoming block and checks the header commitments, so a block
+    /// whose post-state does not match its header leaves the node untouched.
+    /// `base` is the parent's post-state when the block does not extend the head.
+    fn execute_block(&self, block: &Block, base: Option<Snapshot>) -> Result<Execution, String> {
+        // The leader's signature only vouches for the block, not for who sent
+        // each tx, so every tx is authenticated again.
         for tx in &block.txs {
-            let _ = self.dispatch_mut(&tx.call);
-            self.inc_nonce(&tx.call.from);
+            security::verify_tx(tx).map_err(|e| format!("invalid tx {}: {}", tx.tx_hash, e))?;
+        }
+        let beneficiary = hex::decode(&block.validator_pk).map(|pk| pq::address_from_pk(&pk))
+            .map_err(|_| "invalid validator_pk".to_string())?;
+        let exec = match base {
+            None => self.execute(block.height, &beneficiary, &block.txs),
+            Some(Snapshot { mut ctx, mut nonces, .. }) => {
+                // The receipts map only covers the canonical chain; on a side
+                // branch a replayed tx_hash is still caught by its used nonce.
+                let (txs, receipts, rejected) =
+                    execute_txs(&mut ctx, &mut nonces, &self.chain_id, block.height, &beneficiary, &block.txs, |_| false);
+                Execution { ctx, nonces, txs, receipts, rejected }
+            }
+        };
+        if let Some((tx_hash, reason)) = exec.rejected.first() {
+            return Err(format!("invalid tx {}: {}", tx_hash, reason));
+        }
+        let state_root = exec.ctx.state_root();
+        if block.state_root != state_root {
+            return Err(format!("state_root mismatch: header {}, computed {}", block.state_root, state_root));
+        }
+        if block.receipts_root != Receipt::root(&exec.receipts) {
+            return Err("receipts_root mismatch".into());
+        }
+        if block.gas_used != exec.gas_used() {
+            return Err(format!("gas_used mismatch: header {}, computed {}", block.gas_used, exec.gas_used()));
         }
+        Ok(exec)
+    }
 
+    fn commit(&self, block: &Block, exec: Execution, persist: bool) {
+        let prev = std::mem::replace(&mut *self.ctx.lock(), exec.ctx.clone());
+        *self.nonces.lock() = exec.nonces.clone();
+        self.chain.lock().push(block.clone());
+        if persist {
+            if let Err(e) = self.store.commit_block(block, &prev, &exec.ctx) {
+                eprintln!("storage: failed to persist block {}: {}", block.height, e);
+            }
+        }
         {
-            let mut chain = self.chain.lock();
-            chain.push(block.clone());
+            let mut receipts = self.receipts.lock();
+            for r in &exec.receipts {
+                receipts.insert(r.tx_hash.clone(), r.clone());
+            }
         }
-        let _ = storage::append_block(&block);
-        let _ = storage::snapshot_state(&self.ctx.lock(), block.height);
+        let mut tree = self.tree.lock();
+        tree.insert(block.clone(), Snapshot { ctx: exec.ctx, nonces: exec.nonces, receipts: exec.receipts });
+        tree.prune(block.height.saturating_sub(max_reorg_depth()));
+    }
 
-        let committed: std::collections::HashSet<String> =
-            block.txs.iter().map(|t| t.tx_hash.clone()).collect();
+    /// Switches the canonical chain to end at `tip`, a block already in the
+    /// tree: rolls state back to the common ancestor's snapshot, then
+    /// re-commits the winning branch. Txs only in the abandoned blocks go back
+    /// to the mempool.
+    fn reorg(&self, tip: &str) -> Result<(), String> {
+        let branch = {
+            let chain = self.chain.lock();
+            self.tree.lock()
+                .branch(tip, |b| chain.get(b.height as usize).is_some_and(|c| c.hash == b.hash))
+                .ok_or("branch leaves the block tree")?
+        };
+        let fork_height = branch.first().ok_or("tip is already canonical")?.height;
+        if fork_height <= self.finalized_height() {
+            return Err(format!("fork at height {} would revert a finalized block", fork_height));
+        }
+        let ancestor_height = fork_height - 1;
+        let ancestor = self.block(ancestor_height).ok_or("no common ancestor")?;
+        let base = self.tree.lock().snapshot(&ancestor.hash).cloned().ok_or("ancestor state pruned")?;
+
+        let dropped = self.chain.lock().split_off(fork_height as usize);
+        let current = std::mem::replace(&mut *self.ctx.lock(), base.ctx.clone());
+        *self.nonces.lock() = base.nonces;
         {
-            let mut pool = self.mempool.lock();
-            pool.retain(|t| !committed.contains(&t.tx_hash));
+            let mut receipts = self.receipts.lock();
+            for tx in dropped.iter().flat_map(|b| &b.txs) {
+                receipts.remove(&tx.tx_hash);
+            }
         }
+        let kept = self.chain.lock().clone();
+        self.store.revert_to(&kept, &dropped, &current, &base.ctx).map_err(|e| e.to_string())?;
 
+        for b in &branch {
+            let snap = self.tree.lock().snapshot(&b.hash).cloned().ok_or("branch state missing")?;
+            self.commit(b, Execution { ctx: snap.ctx, nonces: snap.nonces, txs: b.txs.clone(), receipts: snap.receipts, rejected: Vec::new() }, true);
+        }
+        println!("reorg: dropped {} blocks from height {}, new head {}", dropped.len(), fork_height, self.last_block().height);
+
+        let nonces = self.nonces.lock().clone();
+        let kept_txs: HashSet<String> = branch.iter().flat_map(|b| &b.txs).map(|t| t.tx_hash.clone()).collect();
+        let mut pool = self.mempool.lock();
+        for tx in dropped.into_iter().flat_map(|b| b.txs) {
+            if !kept_txs.contains(&tx.tx_hash) {
+                let committed = *nonces.get(&tx.call.from).unwrap_or(&0);
+                let _ = pool.insert(tx, committed);
+            }
+        }
+        pool.prune(&nonces);
+        Ok(())
+    }
+
+    /// Remembers the signed header of `block` if the leader scheduled for its
+    /// slot signed it; if that leader already signed a different block for
+    /// the slot, records evidence. Headers from any other key are ignored, so
+    /// at most one header per slot is kept. After a reorg the leader of a
+    /// later slot may rightly sign a second block at a height, so heights
+    /// alone prove nothing.
+    pub fn observe_header(&self, block: &Block) {
+        let Some(parent) = self.known_block(&block.parent) else { return };
+        let leader = match self.poa_config(&parent) {
+            Some(cfg) => cfg.leader(&parent, block.slot).pk.clone(),
+            None => self.validator_pk_hex(),
+        };
+        if !block.validator_pk.eq_ignore_ascii_case(&leader) { return; }
+        let h = block.header();
+        if consensus::verify_header_sig(&h, &self.chain_id).is_err() { return; }
+        let min_height = self.last_block().height.saturating_sub(max_reorg_depth());
+        let mut headers = self.headers.lock();
+        headers.retain(|_, seen| seen.height >= min_height);
+        if h.height < min_height { return; }
+        let key = (h.validator_pk.to_lowercase(), h.slot);
+        match headers.get(&key) {
+            None => { headers.insert(key, h); }
+            Some(first) if first.hash != h.hash => {
+                let mut evidence = self.evidence.lock();
+                // One proof per slot is enough to slash; more would let an
+                // equivocating leader grow the log without bound.
+                if !evidence.iter().any(|e| e.a.slot == h.slot && e.a.validator_pk.eq_ignore_ascii_case(&h.validator_pk)) {
+                    eprintln!("equivocation: validator {} signed two blocks for slot {}", &h.validator_pk[..16.min(h.validator_pk.len())], h.slot);
+                    evidence.push(Evidence { a: first.clone(), b: h });
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    pub fn evidence(&self) -> Vec<Evidence> { self.evidence.lock().clone() }
+
+    /// A recent block by hash, canonical or on a side branch.
+    pub fn known_block(&self, hash: &str) -> Option<Block> {
+        self.tree.lock().get(hash).cloned()
+    }
+
+    pub fn tx(&self, tx_hash: &str) -> Option<(Tx, TxLocation)> {
+        let loc = self.store.tx_location(tx_hash)?;
+        let tx = self.block(loc.height)?.txs.get(loc.index).cloned()?;
+        Some((tx, loc))
+    }
+
+    pub fn account_txs(&self, addr: &str, offset: usize, limit: usize) -> (Vec<String>, usize) {
+        self.store.sender_txs(addr, offset, limit)
+    }
+
+    pub fn receipt(&self, tx_hash: &str) -> Option<Receipt> {
+        self.receipts.lock().get(tx_hash).cloned()
+    }
+
+    /// Imports a block from a peer. Blocks on top of the head extend it; blocks
+    /// on other known parents are validated against that parent's snapshot,
+    /// kept in the tree, and trigger a reorg if fork choice prefers them.
+    pub fn apply_external_block(&self, block: Block) -> Result<Block, String> {
+        self.observe_header(&block);
+        let _import = self.import.lock();
+        let head = self.last_block();
+        if block.parent == head.hash {
+            Self::check_header(&head, &block)?;
+            let exec = self.execute_block(&block, None)?;
+            self.commit(&block, exec, true);
+            let nonces = self.nonces.lock().clone();
+            self.mempool.lock().prune(&nonces);
+        } else {
+            if self.tree.lock().contains(&block.hash) {
+                return Err("block already known".into());
+            }
+            let parent = self.known_block(&block.parent).ok_or("unknown parent")?;
+            Self::check_header(&parent, &block)?;
+            let base = self.tree.lock().snapshot(&parent.hash).cloned().ok_or("parent state pruned")?;
+            let exec = self.execute_block(&block, Some(base))?;
+            self.tree.lock().insert(block.clone(), Snapshot { ctx: exec.ctx, nonces: exec.nonces, receipts: exec.receipts });
+            if blocktree::is_better(&block, &head) {
+                self.reorg(&block.hash)?;
+            }
+        }
+        // Precommits may have arrived before the block did.
+        if let Err(e) = self.try_finalize(block.height, &block.hash) {
+            eprintln!("finality: block {}: {}", block.height, e);
+        }
         Ok(block)
     }
 
+    /// Consensus config for the child of `parent`. Under `QSC_CONSENSUS=pos`
+    /// the validators are the bonded ones in the parent's state, weighted by
+    /// stake; otherwise (and until anyone bonds) the set that the `validators`
+    /// contract schedules for that height, in round-robin.
+    pub fn poa_config(&self, parent: &Block) -> Option<PoAConfig> {
+        let cfg = PoAConfig::from_env()?;
+        Some(match self.tree.lock().snapshot(&parent.hash) {
+            Some(s) => with_validator_set(cfg, &s.ctx, parent.height + 1),
+            None => with_validator_set(cfg, &self.ctx.lock(), parent.height + 1),
+        })
+    }
+
+    /// Public keys whose precommits count towards finality at `height`, with
+    /// their voting power: the consensus validator set, weighted by stake
+    /// under PoS, or just this node when it runs alone.
+    pub fn finality_validators(&self, height: u64) -> Vec<(String, u64)> {
+        let Some(cfg) = PoAConfig::from_env() else { return vec![(self.validator_pk_hex(), 1)] };
+        with_validator_set(cfg, &self.ctx.lock(), height).voters()
+    }
+
+    /// Height of the last final block; genesis is final by definition.
+    pub fn finalized_height(&self) -> u64 {
+        self.certs.lock().keys().next_back().copied().unwrap_or(0)
+    }
+    pub fn cert(&self, height: u64) -> Option<FinalityCert> { self.certs.lock().get(&height).cloned() }
+    pub fn latest_cert(&self) -> Option<FinalityCert> { self.certs.lock().values().next_back().cloned() }
+
+    /// Signs a precommit for `block` if this node is a validator, the block is
+    /// canonical and we have not voted at its height yet. The vote is counted
+    /// locally before being returned for broadcast.
+    pub fn precommit(&self, block: &Block) -> Option<Precommit> {
+        if !self.has_sk() || self.block(block.height)?.hash != block.hash { return None; }
+        let pk = self.validator_pk_hex();
+        if !self.finality_validators(block.height).iter().any(|(v, _)| v.eq_ignore_ascii_case(&pk)) { return None; }
+        {
+            let mut last = self.last_vote.lock();
+            if block.height <= *last { return None; }
+            *last = block.height;
+        }
+        let sig = pq::sign_mldsa3(&Precommit::payload(&self.chain_id, block.height, &block.hash), &self.validator_sk);
+        let p = Precommit { height: block.height, block_hash: block.hash.clone(), validator_pk: pk, sig: hex::encode(sig) };
+        if let Err(e) = self.add_precommit(p.clone()) {
+            eprintln!("finality: own precommit for block {} not counted: {}", block.height, e);
+        }
+        Some(p)
+    }
+
+    /// Records a validator's vote and finalizes its block once a quorum has voted.
+    pub fn add_precommit(&self, p: Precommit) -> Result<(), String> {
+        consensus::verify_precommit(&self.finality_validators(p.height), &self.chain_id, &p)?;
+        if p.height <= self.finalized_height() { return Ok(()); }
+        if p.height > self.last_block().height + max_reorg_depth() {
+            return Err(format!("precommit for height {} is too far ahead", p.height));
+        }
+        if let Some(b) = self.known_block(&p.block_hash) {
+            if b.height != p.height {
+                return Err(format!("precommit for height {} but block is at height {}", p.height, b.height));
+            }
+        }
+        {
+            let mut votes = self.votes.lock();
+            let v = votes.entry((p.height, p.block_hash.clone())).or_default();
+            if v.iter().any(|x| x.validator_pk.eq_ignore_ascii_case(&p.validator_pk)) { return Ok(()); }
+            v.push(p.clone());
+        }
+        let _import = self.import.lock();
+        self.try_finalize(p.height, &p.block_hash)
+    }
+
+    /// Accepts a certificate gathered elsewhere, e.g. from a peer during sync.
+    pub fn add_cert(&self, cert: FinalityCert) -> Result<(), String> {
+        if cert.height <= self.finalized_height() { return Ok(()); }
+        consensus::verify_cert(&self.finality_validators(cert.height), &self.chain_id, &cert)?;
+        if self.known_block(&cert.block_hash).is_none() && self.block(cert.height).is_none_or(|b| b.hash != cert.block_hash) {
+            return Err("certified block unknown".into());
+        }
+        let _import = self.import.lock();
+        self.finalize(cert)
+    }
+
+    fn try_finalize(&self, height: u64, hash: &str) -> Result<(), String> {
+        let key = (height, hash.to_string());
+        let votes = match self.votes.lock().get(&key) { Some(v) => v.clone(), None => return Ok(()) };
+        let (power, total) = consensus::voting_power(&self.finality_validators(height), votes.iter().map(|p| p.validator_pk.as_str()));
+        if !consensus::has_quorum(power, total) { return Ok(()); }
+        // Keep the votes until the block itself shows up; votes that named
+        // the wrong height for it never count.
+        if self.known_block(hash).is_none_or(|b| b.height != height) { return Ok(()); }
+        self.finalize(FinalityCert { height, block_hash: key.1, precommits: votes })
+    }
+
+    /// Marks the certified block, and with it every ancestor, as final. A
+    /// certified block on a side branch becomes canonical first.
+    fn finalize(&self, cert: FinalityCert) -> Result<(), String> {
+        if cert.height <= self.finalized_height() { return Ok(()); }
+        if self.block(cert.height).is_none_or(|b| b.hash != cert.block_hash) {
+            self.reorg(&cert.block_hash)?;
+        }
+        self.store.commit_cert(&cert).map_err(|e| e.to_string())?;
+        self.votes.lock().retain(|(h, _), _| *h > cert.height);
+        self.certs.lock().insert(cert.height, cert);
+        Ok(())
+    }
+
+    /// Reloads stored certificates after `restore`, keeping those that are
+    /// valid and certify a block of the restored chain.
+    fn restore_certs(&self) -> std::io::Result<()> {
+        for cert in self.store.load_certs()? {
+            let canonical = self.block(cert.height).is_some_and(|b| b.hash == cert.block_hash);
+            match consensus::verify_cert(&self.finality_validators(cert.height), &self.chain_id, &cert) {
+                Ok(()) if canonical => { self.certs.lock().insert(cert.height, cert); }
+                Ok(()) => eprintln!("finality: ignoring certificate for non-canonical block {}", cert.height),
+                Err(e) => eprintln!("finality: ignoring certificate for block {}: {}", cert.height, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuilds chain, state and nonces by verifying and replaying blocks
+    /// loaded from disk. Nothing is written back to `chain.jsonl`.
+    fn restore(&self, blocks: Vec<Block>) -> Result<(), String> {
+        for block in blocks {
+            let parent = self.chain.lock().last().cloned();
+            match &parent {
+                Some(p) => {
+                    Self::check_header(p, &block).map_err(|e| format!("block {}: {}", block.height, e))?;
+                    if let Some(cfg) = self.poa_config(p) {
+                        consensus::verify_block_poa(&cfg, &self.chain_id, p, &block).map_err(|e| format!("block {}: {}", block.height, e))?;
+                    }
+                }
+                None => {
+                    if block.height != 0 || block.hash != block.compute_hash() || block.tx_root != block.compute_tx_root() {
+                        return Err("bad genesis".into());
+                    }
+                }
+            }
+            let sig = hex::decode(&block.validator_sig).unwrap_or_default();
+            let pk = hex::decode(&block.validator_pk).unwrap_or_default();
+            if !pq::verify_mldsa3(&SignedHeader::payload(&self.chain_id, &block.hash), &sig, &pk) {
+                return Err(format!("block {}: invalid validator signature", block.height));
+            }
+            let exec = self.execute_block(&block, None).map_err(|e| format!("block {}: {}", block.height, e))?;
+            self.commit(&block, exec, false);
+        }
+        Ok(())
+    }
+
     pub fn validator_info(&self) -> (String, String) {
         ("mldsa3".to_string(), hex::encode(&self.validator_pk))
     }
@@ -126,33 +497,154 @@ impl Runtime {
         contracts::dispatch_query(&ctx, contract, method, args)
     }
 
-    pub fn submit(&self, call: Call, auth: Auth, nonce: u64, chain_id: String, tx_hash: String) {
-        let max_pending: usize = std::env::var("QSC_MAX_PENDING_PER_ADDR").ok().and_then(|s| s.parse().ok()).unwrap_or(100);
-        {
-            let pool = self.mempool.lock();
-            let count = pool.iter().filter(|tx| tx.call.from == call.from).count();
-            if count >= max_pending {
-                eprintln!("rate-limit: dropping tx from {} (pending={})", call.from, count);
-                return;
-            }
+    pub fn pending_nonce(&self, addr: &str) -> u64 {
+        let committed = self.next_nonce(addr);
+        self.mempool.lock().pending_nonce(addr, committed)
+    }
+
+    pub fn submit(&self, tx: Tx) -> Result<(), String> {
+        let committed = self.next_nonce(&tx.call.from);
+        self.mempool.lock().insert(tx, committed)
+    }
+}
+
+fn is_pos() -> bool {
+    std::env::var("QSC_CONSENSUS").is_ok_and(|m| m.eq_ignore_ascii_case("pos"))
+}
+
+/// Replaces the env validator set of `cfg` with the one `ctx` defines for `height`.
+fn with_validator_set(mut cfg: PoAConfig, ctx: &Ctx, height: u64) -> PoAConfig {
+    let bonded = if is_pos() { staking::active_set(ctx) } else { Vec::new() };
+    if !bonded.is_empty() {
+        let (set, stakes) = bonded.into_iter().unzip();
+        cfg.set_validators(set);
+        cfg.stakes = stakes;
+    } else if let Some(set) = validators::set_at(ctx, height) {
+        cfg.set_validators(set);
+    }
+    cfg
+}
+
+/// New tokens minted to the validator of each block (`QSC_BLOCK_REWARD`).
+fn block_reward() -> u64 {
+    std::env::var("QSC_BLOCK_REWARD").ok().and_then(|s| s.parse().ok()).unwrap_or(0)
+}
+
+/// How many blocks back a reorg may reach (`QSC_MAX_REORG_DEPTH`).
+fn max_reorg_depth() -> u64 {
+    std::env::var("QSC_MAX_REORG_DEPTH").ok().and_then(|s| s.parse().ok()).unwrap_or(64)
+}
+
+struct Execution {
+    ctx: Ctx,
+    nonces: HashMap<String, u64>,
+    /// Txs that passed the checks, in execution order
+    txs: Vec<Tx>,
+    receipts: Vec<Receipt>,
+    /// (tx_hash, reason) for txs that were not executed
+    rejected: Vec<(String, String)>,
+}
+
+impl Execution {
+    fn gas_used(&self) -> u64 {
+        self.receipts.iter().map(|r| r.gas_used).sum()
+    }
+}
+
+fn check_tx(tx: &Tx, nonces: &HashMap<String, u64>, chain_id: &str, seen: &HashSet<String>, is_committed: &impl Fn(&str) -> bool) -> Result<(), String> {
+    if tx.chain_id != chain_id {
+        return Err(format!("wrong chain_id: {}", tx.chain_id));
+    }
+    if seen.contains(&tx.tx_hash) || is_committed(&tx.tx_hash) {
+        return Err("duplicate tx_hash".into());
+    }
+    let expected = *nonces.get(&tx.call.from).unwrap_or(&0);
+    if tx.nonce != expected {
+        return Err(format!("bad nonce: expected {}, got {}", expected, tx.nonce));
+    }
+    Ok(())
+}
+
+/// Runs `txs` in order. A tx with a wrong chain_id, an already used tx_hash,
+/// a nonce other than the sender's next one or a fee the sender cannot pay is
+/// rejected: it is not executed, gets no receipt and does not consume the
+/// nonce. Every accepted tx pays its fee and consumes its nonce, even when the
+/// contract call fails; a failed call leaves no state changes and is reported
+/// in its receipt with `status: failed` and the contract error, e.g.
+/// `out of gas` past its `gas_limit`. The fees and `QSC_BLOCK_REWARD`
+/// go to `beneficiary`, the block's validator.
+fn execute_txs(
+    ctx: &mut Ctx,
+    nonces: &mut HashMap<String, u64>,
+    chain_id: &str,
+    height: u64,
+    beneficiary: &str,
+    txs: &[Tx],
+    is_committed: impl Fn(&str) -> bool,
+) -> (Vec<Tx>, Vec<Receipt>, Vec<(String, String)>) {
+    let mut included = Vec::with_capacity(txs.len());
+    let mut receipts = Vec::with_capacity(txs.len());
+    let mut rejected = Vec::new();
+    let mut seen = HashSet::new();
+    let mut fees: u64 = 0;
+    ctx.set_height(height);
+    for tx in txs {
+        if let Err(reason) = check_tx(tx, nonces, chain_id, &seen, &is_committed) {
+            rejected.push((tx.tx_hash.clone(), reason));
+            continue;
         }
-        let tx = Tx { call, timestamp: now_ms(), auth, nonce, chain_id, tx_hash };
-        self.mempool.lock().push(tx);
+        if let Err(e) = token::debit(ctx, &tx.call.from, tx.fee) {
+            rejected.push((tx.tx_hash.clone(), format!("cannot pay fee: {}", e)));
+            continue;
+        }
+        fees = fees.saturating_add(tx.fee);
+        seen.insert(tx.tx_hash.clone());
+        ctx.start_gas(tx.gas_limit);
+        let res = contracts::dispatch_mut(ctx, &tx.call.from, &tx.call.contract, &tx.call.method, &tx.call.args);
+        let gas_used = ctx.stop_gas();
+        *nonces.entry(tx.call.from.clone()).or_insert(0) += 1;
+        let (status, error, result) = match res {
+            Ok(v) => (TxStatus::Success, None, Some(v)),
+            Err(e) => (TxStatus::Failed, Some(e.to_string()), None),
+        };
+        receipts.push(Receipt { tx_hash: tx.tx_hash.clone(), height, index: included.len(), status, error, result, fee: tx.fee, gas_used });
+        included.push(tx.clone());
+    }
+    // Fees were debited from existing balances, so crediting them cannot overflow.
+    let _ = token::credit(ctx, beneficiary, fees);
+    if height > 0 {
+        // Past the max supply the validator only gets the fees.
+        let _ = token::mint_to(ctx, beneficiary, block_reward());
     }
+    (included, receipts, rejected)
 }
 
-pub fn new_shared() -> SharedRuntime {
+pub fn new_shared() -> std::io::Result<SharedRuntime> {
     let chain_id = std::env::var("QSC_CHAIN_ID").unwrap_or_else(|_| "qsc-local".into());
     let (validator_sk, validator_pk) = match (std::env::var("QSC_VALIDATOR_SK"), std::env::var("QSC_VALIDATOR_PK")) {
         (Ok(sk_hex), Ok(pk_hex)) => (hex::decode(sk_hex.trim()).unwrap_or_default(), hex::decode(pk_hex.trim()).unwrap_or_default()),
         _ => pq::keygen_mldsa3(),
     };
+    open_runtime(storage::open()?, chain_id, validator_sk, validator_pk)
+}
 
+/// Builds a runtime over `store`: replays the chain it holds, or starts a new
+/// one from genesis.
+pub fn open_runtime(store: Box<dyn Storage>, chain_id: String, validator_sk: Vec<u8>, validator_pk: Vec<u8>) -> std::io::Result<SharedRuntime> {
     let rt = Arc::new(Runtime {
         ctx: Mutex::new(Ctx::default()),
-        mempool: Mutex::new(Vec::new()),
+        mempool: Mutex::new(Mempool::default()),
         chain: Mutex::new(Vec::new()),
         nonces: Mutex::new(HashMap::new()),
+        receipts: Mutex::new(HashMap::new()),
+        tree: Mutex::new(BlockTree::default()),
+        votes: Mutex::new(HashMap::new()),
+        certs: Mutex::new(BTreeMap::new()),
+        last_vote: Mutex::new(0),
+        headers: Mutex::new(HashMap::new()),
+        evidence: Mutex::new(Vec::new()),
+        import: Mutex::new(()),
+        store,
         chain_id,
         validator_sk,
         validator_pk,
@@ -160,30 +652,478 @@ pub fn new_shared() -> SharedRuntime {
 
     {
         let mut ctx = rt.ctx.lock();
+        ctx.set_chain_id(&rt.chain_id);
         use std::sync::Arc as SyncArc;
         ctx.register(SyncArc::new(crate::contracts::token::Token));
+        ctx.register(SyncArc::new(crate::contracts::tokens::Tokens));
+        ctx.register(SyncArc::new(crate::contracts::nft::Nft));
+        ctx.register(SyncArc::new(crate::contracts::multisig::Multisig));
+        ctx.register(SyncArc::new(crate::contracts::validators::Validators));
+        ctx.register(SyncArc::new(crate::contracts::staking::Staking));
+        ctx.register(SyncArc::new(crate::contracts::evidence::EvidenceContract));
+        // Part of the genesis state, so every node must derive the same roles.
+        let minter = std::env::var("QSC_MINTER_ADDR").unwrap_or_else(|_| {
+            let genesis_pk = PoAConfig::from_env()
+                .and_then(|cfg| cfg.validators.first().and_then(|v| hex::decode(&v.pk).ok()))
+                .unwrap_or_else(|| rt.validator_pk.clone());
+            pq::address_from_pk(&genesis_pk)
+        });
+        let owner = std::env::var("QSC_TOKEN_OWNER").unwrap_or_else(|_| minter.clone());
+        token::init(&mut ctx, &owner, &minter);
     }
 
-    {
-        let mut chain = rt.chain.lock();
-        if chain.is_empty() {
-            let timestamp: u128 = std::env::var("QSC_GENESIS_MS")
-                .ok()
-                .and_then(|s| s.parse().ok())
-                .unwrap_or(0);
-                
-            let parent = "0".repeat(128);
-            let header_str = format!("{}|{}|{}|{}", parent, 0u64, 0usize, timestamp);
-            let hash = hash_hex(header_str.as_bytes());
-            let sig = pq::sign_mldsa3(hash.as_bytes(), &rt.validator_sk);
-            let sig_hex = hex::encode(sig);
-            let pk_hex = hex::encode(&rt.validator_pk);
-            let genesis = Block { height: 0, parent, hash, timestamp, validator_pk: pk_hex, validator_sig: sig_hex, txs: vec![] };
-            chain.push(genesis.clone());
-            let _ = storage::append_block(&genesis);
-            let _ = storage::snapshot_state(&rt.ctx.lock(), 0);
-        }
-    }
-
-    rt
+    let blocks = rt.store.load_chain()?;
+    if !blocks.is_empty() {
+        rt.restore(blocks).map_err(std::io::Error::other)?;
+        let head = rt.last_block();
+        rt.store.finish_restore(&head, &rt.ctx.lock())?;
+        rt.restore_certs()?;
+        // We may have voted up to the head before restarting.
+        *rt.last_vote.lock() = head.height;
+        println!("Restored chain up to block {} from disk", head.height);
+    }
+
+    if rt.chain.lock().is_empty() {
+        let timestamp: u128 = std::env::var("QSC_GENESIS_MS")
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or(0);
+
+        let mut genesis = Block {
+            height: 0,
+            slot: 0,
+            parent: "0".repeat(128),
+            hash: String::new(),
+            timestamp,
+            tx_root: String::new(),
+            state_root: rt.ctx.lock().state_root(),
+            receipts_root: Receipt::root(&[]),
+            validator_pk: hex::encode(&rt.validator_pk),
+            validator_sig: String::new(),
+            gas_used: 0,
+            txs: vec![],
+        };
+        rt.seal(&mut genesis);
+        let exec = rt.execute(0, "", &[]);
+        rt.commit(&genesis, exec, true);
+    }
+
+    Ok(rt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::contracts::testutil::{self, addr};
+    use crate::types::Auth;
+    use serde_json::json;
+
+    fn tx(from: &str, nonce: u64, method: &str, args: serde_json::Value) -> Tx {
+        Tx {
+            call: Call { from: from.into(), contract: "token".into(), method: method.into(), args },
+            timestamp: 0,
+            auth: Auth { alg: "mldsa3".into(), pk: String::new(), sig: String::new() },
+            nonce,
+            fee: 0,
+            gas_limit: crate::types::DEFAULT_GAS_LIMIT,
+            chain_id: "test".into(),
+            tx_hash: format!("{}-{}-{}", from, nonce, method),
+        }
+    }
+
+    /// A token call from the owner of `pk`, signed like `/call` requires.
+    fn signed_tx(sk: &[u8], pk: &[u8], nonce: u64, method: &str, args: serde_json::Value) -> Tx {
+        let mut call = crate::types::RpcCall {
+            from: pq::address_from_pk(pk),
+            contract: "token".into(),
+            method: method.into(),
+            args,
+            alg: "mldsa3".into(),
+            pk: hex::encode(pk),
+            sig: String::new(),
+            nonce,
+            fee: 0,
+            gas_limit: crate::types::DEFAULT_GAS_LIMIT,
+            chain_id: "test".into(),
+        };
+        call.sig = hex::encode(pq::sign_mldsa3(&call.canonical_payload(), sk));
+        call.tx()
+    }
+
+    /// A runtime over the sled store in `dir`.
+    fn open_in(dir: &std::path::Path, sk: &[u8], pk: &[u8]) -> SharedRuntime {
+        let store = Box::new(storage::kv::KvStorage::open(dir).unwrap());
+        open_runtime(store, "test".into(), sk.to_vec(), pk.to_vec()).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsc-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn failed_call_consumes_nonce_and_is_reported() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        let txs = vec![tx(&a, 0, "transfer", json!({"to": b, "amount": 5}))];
+
+        let (included, receipts, rejected) = execute_txs(&mut ctx, &mut nonces, "test", 1, "", &txs, |_| false);
+
+        assert_eq!(included.len(), 1);
+        assert!(rejected.is_empty());
+        assert_eq!(nonces[&a], 1);
+        assert_eq!(receipts[0].status, TxStatus::Failed);
+        assert_eq!(receipts[0].error.as_deref(), Some("underflow"));
+    }
+
+    #[test]
+    fn same_nonce_twice_only_executes_once() {
+        let a = addr('a');
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        let mut second = tx(&a, 0, "mint", json!({"to": a, "amount": 7}));
+        second.tx_hash.push_str("-other");
+        let txs = vec![tx(&a, 0, "mint", json!({"to": a, "amount": 5})), second];
+
+        let (included, receipts, rejected) = execute_txs(&mut ctx, &mut nonces, "test", 1, "", &txs, |_| false);
+
+        assert_eq!(included.len(), 1);
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(rejected.len(), 1);
+        assert!(rejected[0].1.starts_with("bad nonce"));
+        assert_eq!(nonces[&a], 1);
+        assert_eq!(contracts::dispatch_query(&ctx, "token", "total_supply", &json!({})).unwrap(), json!(5));
+    }
+
+    #[test]
+    fn rejects_duplicate_hash_and_wrong_chain_without_consuming_nonce() {
+        let a = addr('a');
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        let committed = tx(&a, 0, "mint", json!({"to": a, "amount": 1}));
+        let mut other_chain = tx(&a, 0, "mint", json!({"to": a, "amount": 1}));
+        other_chain.chain_id = "other".into();
+        other_chain.tx_hash.push_str("-other");
+        let txs = vec![committed.clone(), other_chain];
+
+        let (included, _, rejected) =
+            execute_txs(&mut ctx, &mut nonces, "test", 2, "", &txs, |h| h == committed.tx_hash);
+
+        assert!(included.is_empty());
+        assert_eq!(rejected[0].1, "duplicate tx_hash");
+        assert!(rejected[1].1.starts_with("wrong chain_id"));
+        assert_eq!(nonces.get(&a), None);
+    }
+
+    #[test]
+    fn fees_go_to_beneficiary_and_unpaid_fee_rejects() {
+        let (a, b, v) = (addr('a'), addr('b'), addr('v'));
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        token::mint_to(&mut ctx, &a, 10).unwrap();
+        let mut failing = tx(&a, 0, "transfer", json!({"to": b, "amount": 100}));
+        failing.fee = 3;
+        let mut too_expensive = tx(&b, 0, "transfer", json!({"to": a, "amount": 1}));
+        too_expensive.fee = 1;
+        let txs = vec![failing, too_expensive];
+
+        let (included, receipts, rejected) = execute_txs(&mut ctx, &mut nonces, "test", 1, &v, &txs, |_| false);
+
+        assert_eq!(included.len(), 1);
+        assert_eq!(receipts[0].status, TxStatus::Failed);
+        assert_eq!(receipts[0].fee, 3);
+        assert!(rejected[0].1.starts_with("cannot pay fee"));
+        let balance = |who: &str| contracts::dispatch_query(&ctx, "token", "balance_of", &json!({"who": who})).unwrap();
+        assert_eq!(balance(&a), json!(7));
+        assert_eq!(balance(&v), json!(3));
+        assert_eq!(nonces.get(&b), None);
+    }
+
+    #[test]
+    fn out_of_gas_reverts_call() {
+        let a = addr('a');
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        let mut mint = tx(&a, 0, "mint", json!({"to": a, "amount": 5}));
+        mint.gas_limit = contracts::GAS_CALL + contracts::GAS_READ;
+        let txs = vec![mint];
+
+        let (included, receipts, _) = execute_txs(&mut ctx, &mut nonces, "test", 1, "", &txs, |_| false);
+
+        assert_eq!(included.len(), 1);
+        assert_eq!(receipts[0].status, TxStatus::Failed);
+        assert_eq!(receipts[0].error.as_deref(), Some("out of gas"));
+        assert_eq!(receipts[0].gas_used, txs[0].gas_limit);
+        assert_eq!(nonces[&a], 1);
+        assert_eq!(contracts::dispatch_query(&ctx, "token", "total_supply", &json!({})).unwrap(), json!(0));
+    }
+
+    #[test]
+    fn failed_call_leaves_no_partial_writes() {
+        let (a, b) = (addr('a'), addr('b'));
+        let mut ctx = testutil::ctx(vec![]);
+        let mut nonces = HashMap::new();
+        {
+            let mut ns = ctx.ns_mut("token");
+            ns.insert(a.clone(), json!(10));
+            ns.insert(b.clone(), json!(u64::MAX));
+        }
+        let txs = vec![tx(&a, 0, "transfer", json!({"to": b, "amount": 10}))];
+
+        let (_, receipts, _) = execute_txs(&mut ctx, &mut nonces, "test", 1, "", &txs, |_| false);
+
+        assert_eq!(receipts[0].error.as_deref(), Some("overflow"));
+        assert_eq!(ctx.ns("token").unwrap()[&a], json!(10));
+    }
+
+    #[test]
+    fn nested_checkpoints_revert_inner_writes_only() {
+        let mut ctx = testutil::ctx(vec![]);
+        let outer = ctx.checkpoint();
+        ctx.ns_mut("t").insert("a".into(), json!(1));
+        let inner = ctx.checkpoint();
+        ctx.ns_mut("t").insert("a".into(), json!(2));
+        ctx.ns_mut("t").insert("b".into(), json!(3));
+        ctx.revert(inner);
+        assert_eq!(ctx.ns("t").unwrap().get("a"), Some(&json!(1)));
+        assert_eq!(ctx.ns("t").unwrap().get("b"), None);
+
+        let inner = ctx.checkpoint();
+        ctx.ns_mut("t").insert("b".into(), json!(4));
+        ctx.commit(inner);
+        ctx.revert(outer);
+        assert!(ctx.ns("t").unwrap().is_empty());
+    }
+
+    #[test]
+    fn restart_restores_chain_state_and_nonces() {
+        let dir = temp_dir("restore");
+        let (sk, pk) = pq::keygen_mldsa3();
+        let minter = pq::address_from_pk(&pk);
+        let (head, state_root, tx_hash) = {
+            let rt = open_in(&dir, &sk, &pk);
+            let mint = signed_tx(&sk, &pk, 0, "mint", json!({"to": minter, "amount": 9}));
+            rt.submit(mint.clone()).unwrap();
+            rt.produce_block(1);
+            rt.produce_block(2);
+            let state_root = rt.ctx.lock().state_root();
+            (rt.last_block(), state_root, mint.tx_hash)
+        };
+
+        let rt = open_in(&dir, &sk, &pk);
+        assert_eq!(rt.last_block().hash, head.hash);
+        assert_eq!(rt.ctx.lock().state_root(), state_root);
+        assert_eq!(rt.next_nonce(&minter), 1);
+        assert_eq!(rt.receipt(&tx_hash).unwrap().status, TxStatus::Success);
+        drop(rt);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn reorg_to_longer_branch_rolls_back_state_and_nonces() {
+        let (dir_a, dir_b) = (temp_dir("reorg-a"), temp_dir("reorg-b"));
+        let (sk, pk) = pq::keygen_mldsa3();
+        let minter = pq::address_from_pk(&pk);
+        let (a, b) = (open_in(&dir_a, &sk, &pk), open_in(&dir_b, &sk, &pk));
+        assert_eq!(a.last_block().hash, b.last_block().hash);
+
+        a.submit(signed_tx(&sk, &pk, 0, "mint", json!({"to": minter, "amount": 9}))).unwrap();
+        a.produce_block(1);
+        assert_eq!(a.next_nonce(&minter), 1);
+
+        // A competing branch from later slots that overtakes a's block 1.
+        let side: Vec<Block> = vec![b.produce_block(2), b.produce_block(3)];
+        a.apply_external_block(side[0].clone()).unwrap();
+        assert_eq!(a.last_block().slot, 1);
+        a.apply_external_block(side[1].clone()).unwrap();
+
+        assert_eq!(a.last_block().hash, side[1].hash);
+        assert_eq!(a.block(1).unwrap().hash, side[0].hash);
+        assert_eq!(a.ctx.lock().state_root(), b.ctx.lock().state_root());
+        assert_eq!(a.next_nonce(&minter), 0);
+        assert_eq!(a.dispatch_query("token", "total_supply", &json!({})).unwrap(), json!(0));
+        // The dropped mint is pending again.
+        assert_eq!(a.mempool.lock().len(), 1);
+        drop((a, b));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    fn signed_precommit(sk: &[u8], pk: &[u8], height: u64, block_hash: &str) -> Precommit {
+        let sig = pq::sign_mldsa3(&Precommit::payload("test", height, block_hash), sk);
+        Precommit { height, block_hash: block_hash.into(), validator_pk: hex::encode(pk), sig: hex::encode(sig) }
+    }
+
+    #[test]
+    fn quorum_of_precommits_finalizes_block_at_its_height() {
+        let dir = temp_dir("finality-quorum");
+        let (sk, pk) = pq::keygen_mldsa3();
+        let rt = open_in(&dir, &sk, &pk);
+        rt.produce_block(1);
+        let b2 = rt.produce_block(2);
+        assert_eq!(rt.finalized_height(), 0);
+
+        // A single validator is its own quorum.
+        rt.add_precommit(signed_precommit(&sk, &pk, 2, &b2.hash)).unwrap();
+        assert_eq!(rt.finalized_height(), 2);
+        let cert = rt.cert(2).unwrap();
+        assert_eq!((cert.height, cert.block_hash.as_str(), cert.precommits.len()), (2, b2.hash.as_str(), 1));
+        assert!(rt.votes.lock().is_empty());
+        drop(rt);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn precommit_with_wrong_height_is_rejected_and_does_not_stall_finality() {
+        let (dir_a, dir_b) = (temp_dir("finality-height-a"), temp_dir("finality-height-b"));
+        let (sk, pk) = pq::keygen_mldsa3();
+        let (a, b) = (open_in(&dir_a, &sk, &pk), open_in(&dir_b, &sk, &pk));
+        let b1 = a.produce_block(1);
+        a.produce_block(2);
+
+        // The block is known, so the height must match it.
+        let err = a.add_precommit(signed_precommit(&sk, &pk, 2, &b1.hash)).unwrap_err();
+        assert!(err.contains("height"), "{}", err);
+        assert_eq!(a.finalized_height(), 0);
+
+        // Before the block is known the vote is kept under its own height and
+        // never counts for the block once it arrives at height 1.
+        b.add_precommit(signed_precommit(&sk, &pk, 2, &b1.hash)).unwrap();
+        b.apply_external_block(b1.clone()).unwrap();
+        assert_eq!(b.finalized_height(), 0);
+        b.add_precommit(signed_precommit(&sk, &pk, 1, &b1.hash)).unwrap();
+        assert_eq!(b.finalized_height(), 1);
+        assert_eq!(b.cert(1).unwrap().block_hash, b1.hash);
+        drop((a, b));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    #[test]
+    fn only_two_blocks_for_one_slot_are_evidence() {
+        let (dir_a, dir_b) = (temp_dir("evidence-a"), temp_dir("evidence-b"));
+        let (sk, pk) = pq::keygen_mldsa3();
+        let minter = pq::address_from_pk(&pk);
+        let (a, b) = (open_in(&dir_a, &sk, &pk), open_in(&dir_b, &sk, &pk));
+
+        // Same height, later slot: what an honest leader signs after a reorg.
+        a.produce_block(1);
+        let later = b.produce_block(2);
+        a.apply_external_block(later.clone()).unwrap();
+        assert!(a.evidence().is_empty());
+        let e = Evidence { a: a.block(1).unwrap().header(), b: later.header() };
+        assert_eq!(consensus::verify_evidence(&e, "test").unwrap_err(), "headers for different slots");
+
+        // A second, different block for slot 2 is.
+        let dir_c = temp_dir("evidence-c");
+        let c = open_in(&dir_c, &sk, &pk);
+        c.submit(signed_tx(&sk, &pk, 0, "mint", json!({"to": minter, "amount": 1}))).unwrap();
+        let twin = c.produce_block(2);
+        assert_ne!(twin.hash, later.hash);
+        a.apply_external_block(twin).unwrap();
+        let found = a.evidence();
+        assert_eq!(found.len(), 1);
+        consensus::verify_evidence(&found[0], "test").unwrap();
+        // The signatures are bound to the chain they were made on.
+        assert!(consensus::verify_evidence(&found[0], "other").is_err());
+
+        // A third block from the same leader adds nothing, and a header
+        // signed by a key that does not lead the slot is not even recorded.
+        let mut third = later.clone();
+        third.state_root = "00".repeat(32);
+        third.hash = third.compute_hash();
+        third.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &third.hash), &sk));
+        a.observe_header(&third);
+        let (other_sk, other_pk) = pq::keygen_mldsa3();
+        let mut forged = later.clone();
+        forged.validator_pk = hex::encode(&other_pk);
+        forged.hash = forged.compute_hash();
+        forged.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &forged.hash), &other_sk));
+        a.observe_header(&forged);
+        assert_eq!(a.evidence().len(), 1);
+        assert!(!a.headers.lock().keys().any(|(k, _)| *k == forged.validator_pk));
+        drop((a, b, c));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+        let _ = std::fs::remove_dir_all(&dir_c);
+    }
+
+    #[test]
+    fn blocks_with_unauthenticated_txs_are_rejected() {
+        let (dir_a, dir_b) = (temp_dir("tx-auth-a"), temp_dir("tx-auth-b"));
+        let (sk, pk) = pq::keygen_mldsa3();
+        let (other_sk, other_pk) = pq::keygen_mldsa3();
+        let minter = pq::address_from_pk(&pk);
+        let (a, b) = (open_in(&dir_a, &sk, &pk), open_in(&dir_b, &sk, &pk));
+        a.submit(signed_tx(&sk, &pk, 0, "mint", json!({"to": minter, "amount": 1}))).unwrap();
+        let good = a.produce_block(1);
+
+        // The leader signs the tampered block, so only the tx gives it away.
+        let tampered = |edit: &dyn Fn(&mut Tx)| {
+            let mut block = good.clone();
+            edit(&mut block.txs[0]);
+            a.seal(&mut block);
+            b.apply_external_block(block).err().unwrap()
+        };
+        let err = tampered(&|tx| {
+            tx.call.from = pq::address_from_pk(&other_pk);
+            tx.tx_hash = crate::util::hash_hex(&tx.canonical_payload());
+            tx.auth.sig = hex::encode(pq::sign_mldsa3(&tx.canonical_payload(), &sk));
+        });
+        assert!(err.ends_with("from does not match addr(pk)"), "{}", err);
+        let err = tampered(&|tx| tx.auth.sig = hex::encode(pq::sign_mldsa3(&tx.canonical_payload(), &other_sk)));
+        assert!(err.ends_with("invalid PQ signature"), "{}", err);
+        let err = tampered(&|tx| tx.tx_hash = crate::util::hash_hex(b"something else"));
+        assert!(err.ends_with("tx_hash does not match payload"), "{}", err);
+
+        assert_eq!(b.last_block().height, 0);
+        b.apply_external_block(good).unwrap();
+        drop((a, b));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    #[test]
+    fn precommits_held_before_the_block_finalize_it_on_arrival() {
+        let (dir_a, dir_b) = (temp_dir("finality-early-a"), temp_dir("finality-early-b"));
+        let (sk, pk) = pq::keygen_mldsa3();
+        let (a, b) = (open_in(&dir_a, &sk, &pk), open_in(&dir_b, &sk, &pk));
+        let b1 = a.produce_block(1);
+
+        b.add_precommit(signed_precommit(&sk, &pk, 1, &b1.hash)).unwrap();
+        assert_eq!(b.finalized_height(), 0);
+        // b1 extends b's head, so it takes the fast path.
+        b.apply_external_block(b1.clone()).unwrap();
+        assert_eq!(b.finalized_height(), 1);
+        assert_eq!(b.cert(1).unwrap().block_hash, b1.hash);
+        drop((a, b));
+        let _ = std::fs::remove_dir_all(&dir_a);
+        let _ = std::fs::remove_dir_all(&dir_b);
+    }
+
+    #[test]
+    fn sled_state_tree_holds_genesis_and_block_state() {
+        let dir = temp_dir("sled-state");
+        let (sk, pk) = pq::keygen_mldsa3();
+        let minter = pq::address_from_pk(&pk);
+        let rt = open_in(&dir, &sk, &pk);
+        rt.produce_block(1);
+        rt.submit(signed_tx(&sk, &pk, 0, "mint", json!({"to": minter, "amount": 5}))).unwrap();
+        rt.produce_block(2);
+        let expected: HashMap<Vec<u8>, serde_json::Value> = rt.ctx.lock().state_map().iter()
+            .flat_map(|(ns, kv)| kv.iter().map(move |(k, v)| ([ns.as_bytes(), b"\0", k.as_bytes()].concat(), v.clone())))
+            .collect();
+        drop(rt);
+
+        let db = sled::open(&dir).unwrap();
+        let stored: HashMap<Vec<u8>, serde_json::Value> = db.open_tree("state").unwrap().iter()
+            .map(|kv| { let (k, v) = kv.unwrap(); (k.to_vec(), serde_json::from_slice(&v).unwrap()) })
+            .collect();
+        assert!(stored.contains_key(b"token\0minter".as_slice()));
+        assert_eq!(stored, expected);
+        drop(db);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
 }
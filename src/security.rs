@@ -1,3 +1,7 @@
+use crate::pq;
+use crate::types::Tx;
+use crate::util::hash_hex;
+
 pub fn allowed_sig_algs() -> Vec<String> {
     std::env::var("QSC_SIG_ALGS")
         .unwrap_or_else(|_| "mldsa3".into())
@@ -6,3 +10,36 @@ pub fn allowed_sig_algs() -> Vec<String> {
         .filter(|s| !s.is_empty())
         .collect()
 }
+
+/// Checks a detached signature for one of the supported algorithms.
+pub fn verify_sig(alg: &str, pk: &[u8], sig_hex: &str, payload: &[u8]) -> bool {
+    match alg {
+        "mldsa3" => {
+            let sig = hex::decode(sig_hex).unwrap_or_default();
+            pq::verify_mldsa3(payload, &sig, pk)
+        }
+        _ => false
+    }
+}
+
+/// Full authentication of a tx taken from a block: allowed algorithm, sender
+/// address derived from the key, tx_hash and signature over the canonical
+/// payload (which also covers nonce and chain_id).
+pub fn verify_tx(tx: &Tx) -> Result<(), String> {
+    let alg = tx.auth.alg.to_lowercase();
+    if !allowed_sig_algs().contains(&alg) {
+        return Err(format!("signature algorithm not allowed: {}", tx.auth.alg));
+    }
+    let pk = hex::decode(&tx.auth.pk).map_err(|_| "bad pk hex".to_string())?;
+    if pq::address_from_pk(&pk) != tx.call.from {
+        return Err("from does not match addr(pk)".into());
+    }
+    let payload = tx.canonical_payload();
+    if hash_hex(&payload) != tx.tx_hash {
+        return Err("tx_hash does not match payload".into());
+    }
+    if !verify_sig(&alg, &pk, &tx.auth.sig, &payload) {
+        return Err("invalid PQ signature".into());
+    }
+    Ok(())
+}
@@ -0,0 +1,304 @@
+use super::{block_locations, Storage, TxLocation};
+use crate::contracts::Ctx;
+use crate::types::{Block, FinalityCert};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use crate::util::hash_hex;
+use std::fs::{OpenOptions, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// In-memory lookup tables for transactions, mirrored in `txindex.jsonl`.
+#[derive(Default)]
+struct TxIndex {
+    by_hash: HashMap<String, TxLocation>,
+    by_sender: HashMap<String, Vec<String>>,
+}
+
+impl TxIndex {
+    fn insert(&mut self, loc: TxLocation) {
+        self.by_sender.entry(loc.from.clone()).or_default().push(loc.tx_hash.clone());
+        self.by_hash.insert(loc.tx_hash.clone(), loc);
+    }
+}
+
+/// Original file backend: `chain.jsonl`, `txindex.jsonl` and `finality.jsonl`
+/// are append-only logs, `state.json` a full snapshot rewritten every block.
+pub struct JsonlStorage {
+    dir: PathBuf,
+    index: Mutex<TxIndex>,
+}
+
+fn append_index(dir: &Path, locs: &[TxLocation]) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true).append(true)
+        .open(dir.join("txindex.jsonl"))?;
+    for loc in locs {
+        writeln!(f, "{}", serde_json::to_string(loc).unwrap())?;
+    }
+    Ok(())
+}
+
+fn append_block(dir: &Path, b: &Block) -> std::io::Result<()> {
+    let mut f = OpenOptions::new()
+        .create(true).append(true)
+        .open(dir.join("chain.jsonl"))?;
+    let line = serde_json::to_string(b).unwrap();
+    writeln!(f, "{}", line)?;
+    f.sync_data()?;
+    Ok(())
+}
+
+/// Loads every block from `chain.jsonl`, in file order. A missing file means
+/// a fresh node and yields an empty chain. A torn trailing line (crash while
+/// appending) is cut off so the log ends at the last complete block; damage
+/// anywhere else is an error.
+fn load_chain(dir: &Path) -> std::io::Result<Vec<Block>> {
+    let path = dir.join("chain.jsonl");
+    let raw = match std::fs::read(&path) {
+        Ok(raw) => raw,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut blocks = Vec::new();
+    let mut valid_len = 0;
+    let mut lines = raw.split_inclusive(|&c| c == b'\n').enumerate().peekable();
+    while let Some((i, line)) = lines.next() {
+        let is_last = lines.peek().is_none();
+        let text = String::from_utf8_lossy(line);
+        if text.trim().is_empty() {
+            valid_len += line.len();
+            continue;
+        }
+        match serde_json::from_str::<Block>(&text) {
+            Ok(b) if line.ends_with(b"\n") => {
+                blocks.push(b);
+                valid_len += line.len();
+            }
+            Err(e) if !is_last => {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, format!("chain.jsonl line {}: {}", i + 1, e)));
+            }
+            // Only the last line can lack its newline.
+            _ => {
+                eprintln!("storage: truncating torn trailing line {} of chain.jsonl", i + 1);
+                let f = OpenOptions::new().write(true).open(&path)?;
+                f.set_len(valid_len as u64)?;
+                f.sync_all()?;
+            }
+        }
+    }
+    Ok(blocks)
+}
+
+/// Writes `bytes` to a temp file next to `path`, fsyncs it and renames it over
+/// `path`, so readers see either the old or the new file, never a partial one.
+fn write_atomic(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let tmp = path.with_extension("tmp");
+    {
+        let mut f = File::create(&tmp)?;
+        f.write_all(bytes)?;
+        f.sync_all()?;
+    }
+    std::fs::rename(&tmp, path)?;
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// `state.json` is written first and `params.json` (which carries its checksum)
+/// second; a crash in between leaves a checksum mismatch detected at startup.
+fn snapshot_state(dir: &Path, ctx: &Ctx, height: u64) -> std::io::Result<()> {
+    let s = serde_json::to_string_pretty(&ctx.state_map())?;
+    write_atomic(&dir.join("state.json"), s.as_bytes())?;
+    let params = serde_json::json!({
+        "hash_alg": match crate::util::current_hash_alg() { crate::util::HashAlg::Blake2b512 => "blake2b-512", crate::util::HashAlg::Sha3_512 => "sha3-512" },
+        "sig_algs_allowed": crate::security::allowed_sig_algs(),
+        "chain_id": std::env::var("QSC_CHAIN_ID").unwrap_or_else(|_| "qsc-local".into()),
+        "height": height,
+        "state_checksum": hash_hex(s.as_bytes())
+    });
+    write_atomic(&dir.join("params.json"), serde_json::to_string_pretty(&params).unwrap().as_bytes())
+}
+
+/// True if `state.json` matches the checksum and height recorded in `params.json`.
+fn snapshot_is_valid(dir: &Path, height: u64) -> bool {
+    let state = match std::fs::read(dir.join("state.json")) { Ok(s) => s, Err(_) => return false };
+    let params: serde_json::Value = match std::fs::read(dir.join("params.json")).ok().and_then(|p| serde_json::from_slice(&p).ok()) {
+        Some(p) => p,
+        None => return false,
+    };
+    params.get("state_checksum").and_then(|v| v.as_str()) == Some(hash_hex(&state).as_str())
+        && params.get("height").and_then(|v| v.as_u64()) == Some(height)
+}
+
+impl JsonlStorage {
+    /// Keeps its files in `dir`, which is created if missing.
+    pub fn open(dir: impl AsRef<Path>) -> std::io::Result<Self> {
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self { dir: dir.as_ref().to_path_buf(), index: Mutex::default() })
+    }
+
+    /// Loads `txindex.jsonl` into memory, rebuilding it from `blocks` (as read
+    /// from `chain.jsonl`) when the file is missing, unreadable or out of step
+    /// with it.
+    fn load_tx_index(&self, blocks: &[Block]) -> std::io::Result<()> {
+        let mut idx = TxIndex::default();
+        if let Ok(f) = File::open(self.dir.join("txindex.jsonl")) {
+            for line in BufReader::new(f).lines() {
+                let line = line?;
+                match serde_json::from_str::<TxLocation>(&line) {
+                    Ok(loc) => idx.insert(loc),
+                    Err(_) => { idx = TxIndex::default(); break; }
+                }
+            }
+        }
+        let chain_txs: usize = blocks.iter().map(|b| b.txs.len()).sum();
+        if idx.by_hash.len() != chain_txs {
+            idx = TxIndex::default();
+            let locs: Vec<TxLocation> = blocks.iter().flat_map(block_locations).collect();
+            for loc in &locs { idx.insert(loc.clone()); }
+            let _ = std::fs::remove_file(self.dir.join("txindex.jsonl"));
+            append_index(&self.dir, &locs)?;
+        }
+        *self.index.lock() = idx;
+        Ok(())
+    }
+}
+
+impl Storage for JsonlStorage {
+    fn commit_block(&self, b: &Block, _prev: &Ctx, ctx: &Ctx) -> std::io::Result<()> {
+        append_block(&self.dir, b)?;
+        let locs = block_locations(b);
+        append_index(&self.dir, &locs)?;
+        {
+            let mut idx = self.index.lock();
+            for loc in locs { idx.insert(loc); }
+        }
+        snapshot_state(&self.dir, ctx, b.height)
+    }
+
+    fn revert_to(&self, kept: &[Block], _dropped: &[Block], _from: &Ctx, to: &Ctx) -> std::io::Result<()> {
+        let mut out = String::new();
+        for b in kept {
+            out.push_str(&serde_json::to_string(b).unwrap());
+            out.push('\n');
+        }
+        write_atomic(&self.dir.join("chain.jsonl"), out.as_bytes())?;
+        // Force a rebuild of txindex.jsonl from the surviving chain.
+        let _ = std::fs::remove_file(self.dir.join("txindex.jsonl"));
+        self.load_tx_index(kept)?;
+        snapshot_state(&self.dir, to, kept.last().map_or(0, |b| b.height))
+    }
+
+    fn load_chain(&self) -> std::io::Result<Vec<Block>> {
+        let blocks = load_chain(&self.dir)?;
+        self.load_tx_index(&blocks)?;
+        Ok(blocks)
+    }
+
+    fn finish_restore(&self, head: &Block, ctx: &Ctx) -> std::io::Result<()> {
+        if !snapshot_is_valid(&self.dir, head.height) {
+            eprintln!("storage: state.json is torn or stale, rewriting it at height {}", head.height);
+            snapshot_state(&self.dir, ctx, head.height)?;
+        }
+        Ok(())
+    }
+
+    fn commit_cert(&self, cert: &FinalityCert) -> std::io::Result<()> {
+        let mut f = OpenOptions::new()
+            .create(true).append(true)
+            .open(self.dir.join("finality.jsonl"))?;
+        writeln!(f, "{}", serde_json::to_string(cert).unwrap())?;
+        f.sync_data()
+    }
+
+    fn load_certs(&self) -> std::io::Result<Vec<FinalityCert>> {
+        let f = match File::open(self.dir.join("finality.jsonl")) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        // A torn line only loses that certificate; finality is re-derived from the rest.
+        let mut certs = Vec::new();
+        for line in BufReader::new(f).lines() {
+            if let Ok(cert) = serde_json::from_str(&line?) { certs.push(cert); }
+        }
+        Ok(certs)
+    }
+
+    fn tx_location(&self, tx_hash: &str) -> Option<TxLocation> {
+        self.index.lock().by_hash.get(tx_hash).cloned()
+    }
+
+    fn sender_txs(&self, addr: &str, offset: usize, limit: usize) -> (Vec<String>, usize) {
+        let idx = self.index.lock();
+        match idx.by_sender.get(addr) {
+            Some(v) => (v.iter().skip(offset).take(limit).cloned().collect(), v.len()),
+            None => (Vec::new(), 0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pq;
+    use crate::runtime::{open_runtime, SharedRuntime};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsc-test-jsonl-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn open_in(dir: &Path, sk: &[u8], pk: &[u8]) -> std::io::Result<SharedRuntime> {
+        open_runtime(Box::new(JsonlStorage::open(dir)?), "test".into(), sk.to_vec(), pk.to_vec())
+    }
+
+    #[test]
+    fn a_torn_last_block_is_cut_off_and_other_damage_is_an_error() {
+        let dir = temp_dir("torn");
+        let (sk, pk) = pq::keygen_mldsa3();
+        let rt = open_in(&dir, &sk, &pk).unwrap();
+        rt.produce_block(1);
+        let head = rt.produce_block(2);
+        drop(rt);
+
+        let path = dir.join("chain.jsonl");
+        let complete = std::fs::read(&path).unwrap();
+        let line = serde_json::to_string(&head).unwrap();
+        std::fs::write(&path, [&complete[..], &line.as_bytes()[..line.len() / 2]].concat()).unwrap();
+        let rt = open_in(&dir, &sk, &pk).unwrap();
+        assert_eq!(rt.last_block().hash, head.hash);
+        assert_eq!(std::fs::read(&path).unwrap(), complete);
+        drop(rt);
+
+        let mut damaged = complete.clone();
+        damaged[1] = b'#';
+        std::fs::write(&path, damaged).unwrap();
+        let err = open_in(&dir, &sk, &pk).err().unwrap();
+        assert!(err.to_string().starts_with("chain.jsonl line 1:"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn a_corrupted_snapshot_is_rewritten_on_startup() {
+        let dir = temp_dir("snapshot");
+        let (sk, pk) = pq::keygen_mldsa3();
+        let rt = open_in(&dir, &sk, &pk).unwrap();
+        rt.produce_block(1);
+        drop(rt);
+        let read_state = || serde_json::from_slice::<serde_json::Value>(&std::fs::read(dir.join("state.json")).unwrap()).unwrap();
+        let state = read_state();
+        assert!(snapshot_is_valid(&dir, 1));
+
+        std::fs::write(dir.join("state.json"), b"{\"token\": {}}").unwrap();
+        assert!(!snapshot_is_valid(&dir, 1));
+        let rt = open_in(&dir, &sk, &pk).unwrap();
+        assert!(snapshot_is_valid(&dir, 1));
+        assert_eq!(read_state(), state);
+        drop(rt);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,173 @@
+use super::{block_locations, Storage, TxLocation};
+use crate::contracts::Ctx;
+use crate::types::{Block, FinalityCert};
+use sled::transaction::{ConflictableTransactionError, TransactionError};
+use sled::Transactional;
+use std::path::Path;
+
+/// Embedded database backend. Every block is written in a single sled
+/// transaction spanning all trees, so chain, state and indexes always agree.
+pub struct KvStorage {
+    db: sled::Db,
+    blocks: sled::Tree,
+    state: sled::Tree,
+    txs: sled::Tree,
+    senders: sled::Tree,
+    sender_counts: sled::Tree,
+    certs: sled::Tree,
+}
+
+fn io_err<E: std::fmt::Display>(e: E) -> std::io::Error {
+    std::io::Error::other(e.to_string())
+}
+
+// Keys are `part\0part` so a namespace or address can never run into the next field.
+fn state_key(ns: &str, key: &str) -> Vec<u8> {
+    [ns.as_bytes(), b"\0", key.as_bytes()].concat()
+}
+fn sender_key(addr: &str, seq: u64) -> Vec<u8> {
+    [addr.as_bytes(), b"\0", &seq.to_be_bytes()].concat()
+}
+fn read_u64(v: Option<sled::IVec>) -> u64 {
+    v.and_then(|b| b.as_ref().try_into().ok()).map(u64::from_be_bytes).unwrap_or(0)
+}
+
+impl KvStorage {
+    pub fn open(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let db = sled::open(path).map_err(io_err)?;
+        let tree = |name: &str| db.open_tree(name).map_err(io_err);
+        Ok(Self {
+            blocks: tree("blocks")?,
+            state: tree("state")?,
+            txs: tree("txs")?,
+            senders: tree("senders")?,
+            sender_counts: tree("sender_counts")?,
+            certs: tree("certs")?,
+            db,
+        })
+    }
+}
+
+/// State keys whose value changed (or disappeared) going from `prev` to `ctx`.
+fn state_changes(prev: &Ctx, ctx: &Ctx) -> Vec<(Vec<u8>, Option<Vec<u8>>)> {
+    let mut changes = Vec::new();
+    for (ns, kv) in ctx.state_map() {
+        let old = prev.state_map().get(ns);
+        for (k, v) in kv {
+            if old.and_then(|o| o.get(k)) != Some(v) {
+                changes.push((state_key(ns, k), Some(serde_json::to_vec(v).unwrap())));
+            }
+        }
+    }
+    for (ns, kv) in prev.state_map() {
+        let new = ctx.state_map().get(ns);
+        for k in kv.keys() {
+            if new.is_none_or(|n| !n.contains_key(k)) {
+                changes.push((state_key(ns, k), None));
+            }
+        }
+    }
+    changes
+}
+
+fn tx_err(e: TransactionError<()>) -> std::io::Error {
+    io_err(format!("{:?}", e))
+}
+
+impl Storage for KvStorage {
+    fn commit_block(&self, b: &Block, prev: &Ctx, ctx: &Ctx) -> std::io::Result<()> {
+        // Genesis state is set up before block 0 runs, so `prev` already holds
+        // it; write all of it instead of the (empty) difference.
+        let changes = if b.height == 0 { state_changes(&Ctx::default(), ctx) } else { state_changes(prev, ctx) };
+        let block_bytes = serde_json::to_vec(b).unwrap();
+        let locs = block_locations(b);
+
+        let trees = (&self.blocks, &self.state, &self.txs, &self.senders, &self.sender_counts);
+        trees.transaction(|(blocks, state, txs, senders, counts)| {
+            blocks.insert(&b.height.to_be_bytes(), block_bytes.as_slice())?;
+            for (k, v) in &changes {
+                match v {
+                    Some(v) => { state.insert(k.as_slice(), v.as_slice())?; }
+                    None => { state.remove(k.as_slice())?; }
+                }
+            }
+            for loc in &locs {
+                txs.insert(loc.tx_hash.as_bytes(), serde_json::to_vec(loc).unwrap())?;
+                let seq = read_u64(counts.get(loc.from.as_bytes())?);
+                senders.insert(sender_key(&loc.from, seq), loc.tx_hash.as_bytes())?;
+                counts.insert(loc.from.as_bytes(), &(seq + 1).to_be_bytes())?;
+            }
+            Ok::<(), ConflictableTransactionError<()>>(())
+        }).map_err(tx_err)?;
+        self.db.flush().map_err(io_err)?;
+        Ok(())
+    }
+
+    fn revert_to(&self, _kept: &[Block], dropped: &[Block], from: &Ctx, to: &Ctx) -> std::io::Result<()> {
+        let changes = state_changes(from, to);
+        let trees = (&self.blocks, &self.state, &self.txs, &self.senders, &self.sender_counts);
+        trees.transaction(|(blocks, state, txs, senders, counts)| {
+            for (k, v) in &changes {
+                match v {
+                    Some(v) => { state.insert(k.as_slice(), v.as_slice())?; }
+                    None => { state.remove(k.as_slice())?; }
+                }
+            }
+            // Newest first, so each sender's latest entry is the one removed.
+            for b in dropped.iter().rev() {
+                blocks.remove(&b.height.to_be_bytes())?;
+                for tx in b.txs.iter().rev() {
+                    txs.remove(tx.tx_hash.as_bytes())?;
+                    let seq = read_u64(counts.get(tx.call.from.as_bytes())?).saturating_sub(1);
+                    senders.remove(sender_key(&tx.call.from, seq))?;
+                    counts.insert(tx.call.from.as_bytes(), &seq.to_be_bytes())?;
+                }
+            }
+            Ok::<(), ConflictableTransactionError<()>>(())
+        }).map_err(tx_err)?;
+        self.db.flush().map_err(io_err)?;
+        Ok(())
+    }
+
+    fn load_chain(&self) -> std::io::Result<Vec<Block>> {
+        self.blocks.iter().values()
+            .map(|v| {
+                let v = v.map_err(io_err)?;
+                serde_json::from_slice(&v).map_err(io_err)
+            })
+            .collect()
+    }
+
+    fn commit_cert(&self, cert: &FinalityCert) -> std::io::Result<()> {
+        self.certs.insert(cert.height.to_be_bytes(), serde_json::to_vec(cert).unwrap()).map_err(io_err)?;
+        self.certs.flush().map_err(io_err)?;
+        Ok(())
+    }
+
+    fn load_certs(&self) -> std::io::Result<Vec<FinalityCert>> {
+        self.certs.iter().values()
+            .map(|v| {
+                let v = v.map_err(io_err)?;
+                serde_json::from_slice(&v).map_err(io_err)
+            })
+            .collect()
+    }
+
+    fn tx_location(&self, tx_hash: &str) -> Option<TxLocation> {
+        let v = self.txs.get(tx_hash.as_bytes()).ok()??;
+        serde_json::from_slice(&v).ok()
+    }
+
+    fn sender_txs(&self, addr: &str, offset: usize, limit: usize) -> (Vec<String>, usize) {
+        let total = read_u64(self.sender_counts.get(addr.as_bytes()).ok().flatten()) as usize;
+        let start = sender_key(addr, offset as u64);
+        let end = sender_key(addr, total as u64);
+        let txs = self.senders.range(start..end)
+            .values()
+            .take(limit)
+            .filter_map(|v| v.ok())
+            .map(|v| String::from_utf8_lossy(&v).into_owned())
+            .collect();
+        (txs, total)
+    }
+}
@@ -0,0 +1,136 @@
+use crate::contracts::Ctx;
+use crate::types::{Block, FinalityCert};
+use serde::{Serialize, Deserialize};
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxLocation {
+    pub tx_hash: String,
+    pub from: String,
+    pub height: u64,
+    pub index: usize,
+}
+
+pub trait Storage: Send + Sync {
+    /// Persists a block together with its indexes and the state it produced.
+    /// `prev` is the state before the block, so backends can write only what
+    /// changed. Receipts are not stored: replaying the chain re-derives them,
+    /// checked against each block's `receipts_root`, and a reorg cannot leave
+    /// stale ones behind.
+    fn commit_block(&self, b: &Block, prev: &Ctx, ctx: &Ctx) -> std::io::Result<()>;
+    /// Rolls storage back after a reorg: `kept` is the surviving canonical
+    /// chain, `dropped` the blocks removed above it, `from`/`to` the state at
+    /// the old head and at the last kept block.
+    fn revert_to(&self, kept: &[Block], dropped: &[Block], from: &Ctx, to: &Ctx) -> std::io::Result<()>;
+    /// Every stored block in height order; empty for a fresh node.
+    fn load_chain(&self) -> std::io::Result<Vec<Block>>;
+    /// Called once the loaded chain has been replayed up to `head`, so a
+    /// backend can repair derived files that a crash left behind.
+    fn finish_restore(&self, _head: &Block, _ctx: &Ctx) -> std::io::Result<()> { Ok(()) }
+    fn tx_location(&self, tx_hash: &str) -> Option<TxLocation>;
+    fn commit_cert(&self, cert: &FinalityCert) -> std::io::Result<()>;
+    /// Every stored finality certificate; callers re-verify them.
+    fn load_certs(&self) -> std::io::Result<Vec<FinalityCert>>;
+    /// Hashes of txs sent by `addr` in chain order, paginated; also returns the total.
+    fn sender_txs(&self, addr: &str, offset: usize, limit: usize) -> (Vec<String>, usize);
+}
+
+pub mod jsonl;
+pub mod kv;
+
+pub(crate) fn data_dir() -> PathBuf {
+    let p = std::env::var("QSC_DATA_DIR").unwrap_or_else(|_| "./data".into());
+    let pb = PathBuf::from(p);
+    let _ = create_dir_all(&pb);
+    pb
+}
+
+pub(crate) fn block_locations(b: &Block) -> Vec<TxLocation> {
+    b.txs.iter().enumerate()
+        .map(|(index, tx)| TxLocation { tx_hash: tx.tx_hash.clone(), from: tx.call.from.clone(), height: b.height, index })
+        .collect()
+}
+
+/// Picks the backend from `QSC_STORAGE`: `jsonl` (default) or `sled`.
+pub fn open() -> std::io::Result<Box<dyn Storage>> {
+    match std::env::var("QSC_STORAGE").unwrap_or_else(|_| "jsonl".into()).to_lowercase().as_str() {
+        "sled" | "kv" => Ok(Box::new(kv::KvStorage::open(data_dir().join("db"))?)),
+        "jsonl" => Ok(Box::new(jsonl::JsonlStorage::open(data_dir())?)),
+        other => Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("unknown QSC_STORAGE: {}", other))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Auth, Call, Tx};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("qsc-test-storage-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    /// A block at `height` holding one tx per sender in `from`; storage does
+    /// not check signatures, so none are made.
+    fn block(height: u64, from: &[&str]) -> Block {
+        let txs = from.iter().enumerate().map(|(i, f)| Tx {
+            call: Call { from: f.to_string(), contract: "token".into(), method: "transfer".into(), args: serde_json::json!({}) },
+            timestamp: 0,
+            auth: Auth { alg: "mldsa3".into(), pk: String::new(), sig: String::new() },
+            nonce: i as u64, fee: 0, gas_limit: crate::types::DEFAULT_GAS_LIMIT,
+            chain_id: "test".into(),
+            tx_hash: format!("{}-{}", height, i),
+        }).collect();
+        Block {
+            height, slot: height, parent: String::new(), hash: format!("b{}", height), timestamp: 0,
+            tx_root: String::new(), state_root: String::new(), receipts_root: String::new(),
+            validator_pk: String::new(), validator_sig: String::new(), gas_used: 0, txs,
+        }
+    }
+
+    #[test]
+    fn sender_txs_pages_stay_in_bounds() {
+        let (jsonl_dir, kv_dir) = (temp_dir("pages-jsonl"), temp_dir("pages-kv"));
+        let stores: Vec<Box<dyn Storage>> = vec![
+            Box::new(jsonl::JsonlStorage::open(&jsonl_dir).unwrap()),
+            Box::new(kv::KvStorage::open(&kv_dir).unwrap()),
+        ];
+        for store in stores {
+            store.commit_block(&block(1, &["a", "b", "a"]), &Ctx::default(), &Ctx::default()).unwrap();
+            store.commit_block(&block(2, &["a", "a"]), &Ctx::default(), &Ctx::default()).unwrap();
+            assert_eq!(store.sender_txs("a", 0, 2), (vec!["1-0".to_string(), "1-2".into()], 4));
+            assert_eq!(store.sender_txs("a", 3, 10), (vec!["2-1".to_string()], 4));
+            assert_eq!(store.sender_txs("a", 1, 0), (Vec::new(), 4));
+            assert_eq!(store.sender_txs("a", 4, 10), (Vec::new(), 4));
+            assert_eq!(store.sender_txs("a", usize::MAX, usize::MAX), (Vec::new(), 4));
+            assert_eq!(store.sender_txs("c", 0, 10), (Vec::new(), 0));
+        }
+        let _ = std::fs::remove_dir_all(&jsonl_dir);
+        let _ = std::fs::remove_dir_all(&kv_dir);
+    }
+
+    #[test]
+    fn a_missing_or_stale_txindex_is_rebuilt_from_the_chain() {
+        let dir = temp_dir("txindex");
+        let store = jsonl::JsonlStorage::open(&dir).unwrap();
+        store.commit_block(&block(1, &["a", "b"]), &Ctx::default(), &Ctx::default()).unwrap();
+        store.commit_block(&block(2, &["a"]), &Ctx::default(), &Ctx::default()).unwrap();
+        let index = std::fs::read_to_string(dir.join("txindex.jsonl")).unwrap();
+        drop(store);
+
+        for damage in [None, Some(index.lines().next().unwrap().to_string() + "\n")] {
+            match &damage {
+                None => std::fs::remove_file(dir.join("txindex.jsonl")).unwrap(),
+                Some(partial) => std::fs::write(dir.join("txindex.jsonl"), partial).unwrap(),
+            }
+            let store = jsonl::JsonlStorage::open(&dir).unwrap();
+            assert_eq!(store.load_chain().unwrap().len(), 2);
+            assert_eq!(store.tx_location("2-0").map(|l| (l.height, l.index)), Some((2, 0)));
+            assert_eq!(store.sender_txs("a", 0, 10), (vec!["1-0".to_string(), "2-0".into()], 2));
+            assert_eq!(std::fs::read_to_string(dir.join("txindex.jsonl")).unwrap().lines().count(), 3);
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
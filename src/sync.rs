@@ -0,0 +1,202 @@
+use crate::consensus::{self, PoAConfig};
+use crate::runtime::SharedRuntime;
+use crate::types::{Block, FinalityCert, Precommit};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Max blocks requested per `GET /blocks` call (and served by it).
+pub const BATCH: u64 = 100;
+
+static SYNCING: AtomicBool = AtomicBool::new(false);
+
+async fn fetch_head(client: &reqwest::Client, url: &str) -> Result<Block, String> {
+    client.get(format!("{}/head", url)).send().await.map_err(|e| e.to_string())?
+        .json::<Block>().await.map_err(|e| e.to_string())
+}
+
+async fn fetch_range(client: &reqwest::Client, url: &str, from: u64, to: u64) -> Result<Vec<Block>, String> {
+    client.get(format!("{}/blocks?from={}&to={}", url, from, to)).send().await.map_err(|e| e.to_string())?
+        .json::<Vec<Block>>().await.map_err(|e| e.to_string())
+}
+
+async fn fetch_cert(client: &reqwest::Client, url: &str) -> Result<Option<FinalityCert>, String> {
+    let r = client.get(format!("{}/finality", url)).send().await.map_err(|e| e.to_string())?;
+    if r.status() == reqwest::StatusCode::NOT_FOUND { return Ok(None); }
+    r.json::<FinalityCert>().await.map(Some).map_err(|e| e.to_string())
+}
+
+/// The parent of `b` if we have it, either in the block tree or as an older
+/// canonical block that has already been pruned from the tree.
+fn known_parent(rt: &SharedRuntime, b: &Block) -> Option<Block> {
+    rt.known_block(&b.parent)
+        .or_else(|| rt.block(b.height.checked_sub(1)?).filter(|p| p.hash == b.parent))
+}
+
+/// Downloads and applies the blocks we are missing from one peer, verifying
+/// each against the PoA rules before applying it. If the peer is on another
+/// branch, steps back until its blocks attach to one we know, so fork choice
+/// can decide. Returns how many were applied.
+async fn sync_from(rt: &SharedRuntime, cfg: &PoAConfig, client: &reqwest::Client, url: &str) -> Result<u64, String> {
+    let target = fetch_head(client, url).await?.height;
+    let mut applied = 0;
+    let mut from = rt.last_block().height + 1;
+    while from <= target {
+        let to = target.min(from + BATCH - 1);
+        let blocks = fetch_range(client, url, from, to).await?;
+        let first = blocks.first().ok_or_else(|| format!("peer returned no blocks for {}..={}", from, to))?;
+        if known_parent(rt, first).is_none() {
+            if from <= 1 { return Err("peer chain does not share our genesis".into()); }
+            from = from.saturating_sub(BATCH).max(1);
+            continue;
+        }
+        for b in blocks {
+            from = b.height + 1;
+            if rt.known_block(&b.hash).is_some() || rt.block(b.height).is_some_and(|c| c.hash == b.hash) { continue; }
+            let parent = known_parent(rt, &b).ok_or_else(|| format!("block {}: unknown parent", b.height))?;
+            let cfg = rt.poa_config(&parent).unwrap_or_else(|| cfg.clone());
+            consensus::verify_block_poa(&cfg, &rt.chain_id, &parent, &b).map_err(|e| format!("block {}: {}", b.height, e))?;
+            rt.apply_external_block(b)?;
+            applied += 1;
+        }
+    }
+    Ok(applied)
+}
+
+/// Catches up with the highest peer in `cfg.peers`. Only one sync runs at
+/// a time; concurrent calls return immediately.
+pub async fn sync_with_peers(rt: SharedRuntime, cfg: PoAConfig) {
+    if SYNCING.swap(true, Ordering::SeqCst) { return; }
+    // Peers are the validators governing the block after our head.
+    let cfg = rt.poa_config(&rt.last_block()).unwrap_or(cfg);
+    let client = reqwest::Client::new();
+    let me = rt.validator_pk_hex();
+    for v in &cfg.peers {
+        if v.pk.eq_ignore_ascii_case(&me) { continue; }
+        let url = v.url.trim_end_matches('/');
+        match sync_from(&rt, &cfg, &client, url).await {
+            Ok(0) => {}
+            Ok(n) => println!("sync: applied {} blocks from {}, head is {}", n, v.id, rt.last_block().height),
+            Err(e) => eprintln!("sync: {} failed: {}", v.id, e),
+        }
+        // Blocks alone do not carry finality; take the peer's latest certificate too.
+        if let Ok(Some(cert)) = fetch_cert(&client, url).await {
+            if let Err(e) = rt.add_cert(cert) {
+                eprintln!("sync: certificate from {} rejected: {}", v.id, e);
+            }
+        }
+    }
+    SYNCING.store(false, Ordering::SeqCst);
+}
+
+/// Sends this node's precommit to every other validator.
+pub async fn broadcast_precommit(client: &reqwest::Client, cfg: &PoAConfig, me: &str, p: &Precommit) {
+    for v in &cfg.peers {
+        if v.pk.eq_ignore_ascii_case(me) { continue; }
+        let url = format!("{}/consensus/precommit", v.url.trim_end_matches('/'));
+        match client.post(&url).json(p).send().await {
+            Ok(r) if !r.status().is_success() => {
+                let body = r.text().await.unwrap_or_default();
+                eprintln!("precommit for block {} to {} rejected: {}", p.height, v.id, body);
+            }
+            Err(e) => eprintln!("precommit for block {} to {} failed: {}", p.height, v.id, e),
+            Ok(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::Validator;
+    use crate::pq;
+    use crate::runtime::open_runtime;
+    use crate::storage::kv::KvStorage;
+    use crate::types::SignedHeader;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use std::collections::HashMap;
+
+    fn open(name: &str, sk: &[u8], pk: &[u8]) -> (SharedRuntime, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("qsc-test-sync-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let rt = open_runtime(Box::new(KvStorage::open(&dir).unwrap()), "test".into(), sk.to_vec(), pk.to_vec()).unwrap();
+        (rt, dir)
+    }
+
+    fn config(pk: &[u8]) -> PoAConfig {
+        let me = Validator { id: "v0".into(), url: String::new(), pk: hex::encode(pk) };
+        PoAConfig { peers: vec![me.clone()], validators: vec![me], slot_ms: 1000, genesis_ms: 0, max_drift_ms: 500, stakes: Vec::new() }
+    }
+
+    /// A peer chain of `slots.len()` blocks on top of genesis, timed and
+    /// signed so that it passes `verify_block_poa` under `cfg`.
+    fn peer_chain(name: &str, sk: &[u8], pk: &[u8], cfg: &PoAConfig, slots: &[u64]) -> Vec<Block> {
+        let (peer, dir) = open(name, sk, pk);
+        let mut chain = vec![peer.block(0).unwrap()];
+        for &slot in slots {
+            let mut b = peer.produce_block(slot);
+            b.parent = chain.last().unwrap().hash.clone();
+            b.timestamp = cfg.slot_start(slot);
+            b.hash = b.compute_hash();
+            b.validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &b.hash), sk));
+            chain.push(b);
+        }
+        drop(peer);
+        let _ = std::fs::remove_dir_all(&dir);
+        chain
+    }
+
+    /// Serves `chain` the way a node does, on a free local port.
+    fn serve(chain: Vec<Block>) -> String {
+        let chain = web::Data::new(chain);
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(chain.clone())
+                .route("/head", web::get().to(|c: web::Data<Vec<Block>>| async move { HttpResponse::Ok().json(c.last()) }))
+                .route("/blocks", web::get().to(|c: web::Data<Vec<Block>>, q: web::Query<HashMap<String, u64>>| async move {
+                    let blocks: Vec<&Block> = c.iter().filter(|b| b.height >= q["from"] && b.height <= q["to"]).collect();
+                    HttpResponse::Ok().json(blocks)
+                }))
+                .route("/finality", web::get().to(HttpResponse::NotFound))
+        }).workers(1).bind(("127.0.0.1", 0)).unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        url
+    }
+
+    #[actix_web::test]
+    async fn sync_steps_back_to_the_common_ancestor() {
+        let (sk, pk) = pq::keygen_mldsa3();
+        let cfg = config(&pk);
+        let chain = peer_chain("fork-peer", &sk, &pk, &cfg, &[4, 5, 6, 7, 8]);
+        let url = serve(chain.clone());
+        let (rt, dir) = open("fork", &sk, &pk);
+        for slot in 1..=3 { rt.produce_block(slot); }
+
+        // Block 4 of the peer does not attach to our block 3.
+        let applied = sync_from(&rt, &cfg, &reqwest::Client::new(), &url).await.unwrap();
+        assert_eq!(applied, 5);
+        assert_eq!(rt.last_block().hash, chain[5].hash);
+        assert_eq!(rt.block(1).unwrap().hash, chain[1].hash);
+        drop(rt);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[actix_web::test]
+    async fn sync_stops_at_an_invalid_block() {
+        let (sk, pk) = pq::keygen_mldsa3();
+        let cfg = config(&pk);
+        let mut chain = peer_chain("invalid-peer", &sk, &pk, &cfg, &[1, 2, 3]);
+        // Block 2 is re-signed by a key that does not lead its slot.
+        let (other_sk, other_pk) = pq::keygen_mldsa3();
+        chain[2].validator_pk = hex::encode(&other_pk);
+        chain[2].hash = chain[2].compute_hash();
+        chain[2].validator_sig = hex::encode(pq::sign_mldsa3(&SignedHeader::payload("test", &chain[2].hash), &other_sk));
+        let url = serve(chain.clone());
+        let (rt, dir) = open("invalid", &sk, &pk);
+
+        let err = sync_from(&rt, &cfg, &reqwest::Client::new(), &url).await.unwrap_err();
+        assert!(err.starts_with("block 2: unexpected leader for slot 2"), "{}", err);
+        assert_eq!(rt.last_block().hash, chain[1].hash);
+        drop(rt);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,4 +1,6 @@
 use serde::{Serialize, Deserialize};
+use crate::merkle;
+use crate::util::hash_hex;
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Call {
@@ -21,21 +23,179 @@ pub struct Tx {
     pub timestamp: u128,
     pub auth: Auth,
     pub nonce: u64,
+    /// Paid in `token` by the sender to the block's validator, even if the call fails.
+    #[serde(default)]
+    pub fee: u64,
+    /// Most gas the call may use; past it the call fails and is reverted.
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
     pub chain_id: String,
     pub tx_hash: String,
 }
 
+/// Gas limit of txs that do not set one.
+pub const DEFAULT_GAS_LIMIT: u64 = 100_000;
+
+fn default_gas_limit() -> u64 { DEFAULT_GAS_LIMIT }
+
+/// The exact bytes a sender signs; `tx_hash` is their hash.
+pub fn canonical_payload(call: &Call, nonce: u64, fee: u64, gas_limit: u64, chain_id: &str) -> Vec<u8> {
+    let payload = serde_json::json!({
+        "from": call.from,
+        "nonce": nonce,
+        "fee": fee,
+        "gas_limit": gas_limit,
+        "chain_id": chain_id,
+        "contract": call.contract,
+        "method": call.method,
+        "args": call.args,
+    });
+    serde_json::to_vec(&payload).unwrap()
+}
+
+impl Tx {
+    pub fn canonical_payload(&self) -> Vec<u8> {
+        canonical_payload(&self.call, self.nonce, self.fee, self.gas_limit, &self.chain_id)
+    }
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Block {
     pub height: u64,
+    pub slot: u64,
     pub parent: String,
     pub hash: String,
     pub timestamp: u128,
+    pub tx_root: String,
+    pub state_root: String,
+    pub receipts_root: String,
     pub validator_pk: String,
     pub validator_sig: String,
+    /// Sum of the receipts' `gas_used`, committed through `receipts_root`.
+    #[serde(default)]
+    pub gas_used: u64,
     pub txs: Vec<Tx>,
 }
 
+impl Block {
+    /// Hash of the header fields; this is what the validator signs.
+    pub fn compute_hash(&self) -> String {
+        self.header().compute_hash()
+    }
+    pub fn header(&self) -> SignedHeader {
+        SignedHeader {
+            height: self.height,
+            slot: self.slot,
+            parent: self.parent.clone(),
+            hash: self.hash.clone(),
+            timestamp: self.timestamp,
+            ntx: self.txs.len(),
+            tx_root: self.tx_root.clone(),
+            state_root: self.state_root.clone(),
+            receipts_root: self.receipts_root.clone(),
+            validator_pk: self.validator_pk.clone(),
+            validator_sig: self.validator_sig.clone(),
+        }
+    }
+    pub fn compute_tx_root(&self) -> String {
+        let leaves: Vec<String> = self.txs.iter().map(|t| t.tx_hash.clone()).collect();
+        merkle::merkle_root(&leaves)
+    }
+}
+
+/// A block without its txs: enough to check the hash and leader signature.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignedHeader {
+    pub height: u64,
+    pub slot: u64,
+    pub parent: String,
+    pub hash: String,
+    pub timestamp: u128,
+    pub ntx: usize,
+    pub tx_root: String,
+    pub state_root: String,
+    pub receipts_root: String,
+    pub validator_pk: String,
+    pub validator_sig: String,
+}
+
+impl SignedHeader {
+    /// Bytes the leader signs for a block hash. The chain id is included so
+    /// that a header cannot be shown as evidence on a different network.
+    pub fn payload(chain_id: &str, hash: &str) -> Vec<u8> {
+        format!("block|{}|{}", chain_id, hash).into_bytes()
+    }
+    pub fn compute_hash(&self) -> String {
+        let header_str = format!(
+            "{}|{}|{}|{}|{}|{}|{}|{}",
+            self.parent, self.height, self.slot, self.ntx, self.timestamp, self.tx_root, self.state_root, self.receipts_root
+        );
+        hash_hex(header_str.as_bytes())
+    }
+}
+
+/// Two different headers signed by the same validator for the same slot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Evidence {
+    pub a: SignedHeader,
+    pub b: SignedHeader,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxStatus { Success, Failed }
+
+/// Outcome of an included tx. A `Failed` call still consumed the sender's nonce.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Receipt {
+    pub tx_hash: String,
+    pub height: u64,
+    pub index: usize,
+    pub status: TxStatus,
+    pub error: Option<String>,
+    /// JSON returned by the contract, e.g. the `Transfer` event
+    pub result: Option<serde_json::Value>,
+    #[serde(default)]
+    pub fee: u64,
+    #[serde(default)]
+    pub gas_used: u64,
+}
+
+impl Receipt {
+    pub fn hash(&self) -> String {
+        hash_hex(&serde_json::to_vec(self).unwrap())
+    }
+    pub fn root(receipts: &[Receipt]) -> String {
+        let leaves: Vec<String> = receipts.iter().map(|r| r.hash()).collect();
+        merkle::merkle_root(&leaves)
+    }
+}
+
+/// A validator's ML-DSA-3 vote that `block_hash` at `height` is final.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Precommit {
+    pub height: u64,
+    pub block_hash: String,
+    pub validator_pk: String,
+    pub sig: String,
+}
+
+impl Precommit {
+    /// Bytes the validator signs; binding the chain id keeps votes from being
+    /// replayed on another network.
+    pub fn payload(chain_id: &str, height: u64, block_hash: &str) -> Vec<u8> {
+        format!("precommit|{}|{}|{}", chain_id, height, block_hash).into_bytes()
+    }
+}
+
+/// Precommits from more than 2/3 of the validators for one block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FinalityCert {
+    pub height: u64,
+    pub block_hash: String,
+    pub precommits: Vec<Precommit>,
+}
+
 #[derive(Clone, Serialize, Deserialize)]
 pub struct RpcCall {
     pub from: String,
@@ -46,5 +206,39 @@ pub struct RpcCall {
     pub pk: String,
     pub sig: String,
     pub nonce: u64,
+    #[serde(default)]
+    pub fee: u64,
+    #[serde(default = "default_gas_limit")]
+    pub gas_limit: u64,
     pub chain_id: String,
 }
+
+impl RpcCall {
+    pub fn call(&self) -> Call {
+        Call {
+            from: self.from.clone(),
+            contract: self.contract.clone(),
+            method: self.method.clone(),
+            args: self.args.clone(),
+        }
+    }
+    pub fn auth(&self) -> Auth {
+        Auth { alg: self.alg.clone(), pk: self.pk.clone(), sig: self.sig.clone() }
+    }
+    pub fn canonical_payload(&self) -> Vec<u8> {
+        canonical_payload(&self.call(), self.nonce, self.fee, self.gas_limit, &self.chain_id)
+    }
+    /// The mempool tx for this call, hashed over its canonical payload.
+    pub fn tx(&self) -> Tx {
+        Tx {
+            call: self.call(),
+            timestamp: crate::util::now_ms(),
+            auth: self.auth(),
+            nonce: self.nonce,
+            fee: self.fee,
+            gas_limit: self.gas_limit,
+            chain_id: self.chain_id.clone(),
+            tx_hash: hash_hex(&self.canonical_payload()),
+        }
+    }
+}